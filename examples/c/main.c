@@ -0,0 +1,51 @@
+/* Embeds Rickroll in C: evaluates programs, calls back into C, reads errors. */
+
+#include <stdio.h>
+
+#include "rickroust.h"
+
+/* `scale(x)` multiplies by the factor it was registered with */
+static int scale(void *user_data, const int64_t *args, uintptr_t argc, int64_t *result) {
+    if (argc != 1) {
+        return 2;
+    }
+    *result = args[0] * *(const int64_t *)user_data;
+    return RR_OK;
+}
+
+static void eval(RrInterpreter *interp, const char *source) {
+    if (rr_eval(interp, source) == RR_OK) {
+        printf("printed:\n%s", rr_output(interp));
+        printf("result: %s\n", rr_result(interp));
+    } else {
+        printf("error at %zu:%zu: %s\n", (size_t)rr_error_line(interp),
+               (size_t)rr_error_col(interp), rr_error_message(interp));
+    }
+}
+
+int main(void) {
+    RrInterpreter *interp = rr_interpreter_new();
+    int64_t factor = 10;
+    rr_register(interp, "scale", scale, &factor);
+
+    eval(interp, "print 6 * 7\nprint scale(4) + 2");
+    eval(interp, "scale(1, 2)");
+    eval(interp, "print 1 / 0");
+    eval(interp, "print (1 +");
+
+    int64_t value;
+    if (rr_eval(interp, "2 ** 20") == RR_OK && rr_result_int(interp, &value) == RR_OK) {
+        printf("as int: %lld\n", (long long)value);
+    }
+
+    /* interpreters are independent, and any number can be alive at once */
+    RrInterpreter *other = rr_interpreter_new();
+    int64_t other_factor = 3;
+    rr_register(other, "scale", scale, &other_factor);
+    eval(other, "print scale(5)");
+    eval(interp, "print scale(5)");
+    rr_interpreter_free(other);
+
+    rr_interpreter_free(interp);
+    return 0;
+}
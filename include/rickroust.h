@@ -0,0 +1,77 @@
+/* The Rickroll C API, generated by cbindgen from src/capi.rs. Don't edit by hand. */
+
+#ifndef RICKROUST_H
+#define RICKROUST_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define RR_OK 0
+
+#define RR_ERROR 1
+
+// An interpreter whose printed output is captured rather than written to stdout.
+typedef struct RrInterpreter RrInterpreter;
+
+// A C function callable from Rickroll. It gets `argc` int arguments in `args`,
+// writes its value to `result` and returns `RR_OK`, or fails with anything else.
+typedef int (*RrNativeFn)(void *user_data, const int64_t *args, uintptr_t argc, int64_t *result);
+
+RrInterpreter *rr_interpreter_new(void);
+
+// # Safety
+// `interp` must come from `rr_interpreter_new` and not be used afterwards. NULL is ignored.
+void rr_interpreter_free(RrInterpreter *interp);
+
+// Parses and runs `source`, returning `RR_OK` or `RR_ERROR`.
+//
+// # Safety
+// `interp` must be a live interpreter and `source` a NUL-terminated string.
+int rr_eval(RrInterpreter *interp, const char *source);
+
+// The value of the last successful `rr_eval`, as Rickroll prints it, or NULL.
+//
+// # Safety
+// `interp` must be a live interpreter.
+const char *rr_result(const RrInterpreter *interp);
+
+// Writes the value of the last successful `rr_eval` to `out` and returns `RR_OK`
+// if it's an int that fits, `RR_ERROR` otherwise.
+//
+// # Safety
+// `interp` must be a live interpreter and `out` writable.
+int rr_result_int(const RrInterpreter *interp, int64_t *out);
+
+// Why the last `rr_eval` failed, or NULL if it didn't.
+//
+// # Safety
+// `interp` must be a live interpreter.
+const char *rr_error_message(const RrInterpreter *interp);
+
+// Line of the last error, from 1, or 0 if it isn't at any one place.
+//
+// # Safety
+// `interp` must be a live interpreter.
+uintptr_t rr_error_line(const RrInterpreter *interp);
+
+// Column of the last error, from 1, or 0 if it isn't at any one place.
+//
+// # Safety
+// `interp` must be a live interpreter.
+uintptr_t rr_error_col(const RrInterpreter *interp);
+
+// What the last `rr_eval` printed.
+//
+// # Safety
+// `interp` must be a live interpreter.
+const char *rr_output(const RrInterpreter *interp);
+
+// Makes `function` callable from Rickroll as `name`, replacing any builtin of that name.
+// `user_data` is passed back to it on every call.
+//
+// # Safety
+// `interp` must be a live interpreter, `name` a NUL-terminated string, and
+// `user_data` valid for as long as `function` may be called.
+int rr_register(RrInterpreter *interp, const char *name, RrNativeFn function, void *user_data);
+
+#endif /* RICKROUST_H */
@@ -0,0 +1,166 @@
+use std::str::FromStr;
+
+use crate::bigint::{Int, Meter};
+use crate::interpreter::ErrorKind;
+use crate::lexer::Token;
+
+/// What integer arithmetic does when a result doesn't fit in an `i32`.
+/// Both backends follow the same rules, so a program prints the same thing
+/// whether it's interpreted or compiled, in debug or in release.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Stop with an "integer overflow" runtime error
+    #[default]
+    Checked,
+    /// Two's complement wrap-around
+    Wrapping,
+    /// Clamp to `i32::MIN`/`i32::MAX`
+    Saturating,
+    /// Never overflow, grow into a big integer instead.
+    /// Only the interpreter supports this one.
+    Arbitrary,
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "checked" => Ok(Overflow::Checked),
+            "wrapping" => Ok(Overflow::Wrapping),
+            "saturating" => Ok(Overflow::Saturating),
+            "arbitrary" => Ok(Overflow::Arbitrary),
+            _ => Err(format!(
+                "Unknown overflow mode `{}`, expected `checked`, `wrapping`, `saturating` or `arbitrary`",
+                s
+            )),
+        }
+    }
+}
+
+impl Overflow {
+    /// Every mode, numbered by its position in `.rrc` files, snapshots and calls
+    /// from compiled code into the runtime.
+    pub const ALL: [Overflow; 4] = [
+        Overflow::Checked,
+        Overflow::Wrapping,
+        Overflow::Saturating,
+        Overflow::Arbitrary,
+    ];
+
+    /// The position of this mode in `ALL`.
+    pub fn index(self) -> u8 {
+        Self::ALL.iter().position(|&mode| mode == self).unwrap() as u8
+    }
+
+    /// Looks for a `#pragma overflow <mode>` line in `source`.
+    pub fn from_pragma(source: &str) -> Result<Option<Self>, String> {
+        let mut mode = None;
+        for line in source.lines() {
+            let Some(pragma) = line.trim_start().strip_prefix("#pragma") else {
+                continue;
+            };
+            match pragma.split_whitespace().collect::<Vec<_>>()[..] {
+                ["overflow", value] => mode = Some(value.parse()?),
+                _ => return Err(format!("Unknown pragma: `{}`", line.trim())),
+            }
+        }
+        Ok(mode)
+    }
+
+    /// Brings an integer (e.g. a long literal) into the range of this mode.
+    pub fn narrow(self, value: &Int) -> Result<Int, ErrorKind> {
+        let Int::Big(big) = value else {
+            return Ok(value.clone());
+        };
+        match self {
+            Overflow::Checked => Err(ErrorKind::Overflow),
+            Overflow::Wrapping => Ok(Int::Small(big.wrapping_to_i32())),
+            Overflow::Saturating if big.is_negative() => Ok(Int::Small(i32::MIN)),
+            Overflow::Saturating => Ok(Int::Small(i32::MAX)),
+            Overflow::Arbitrary => Ok(value.clone()),
+        }
+    }
+
+    /// `lhs op rhs`, with `meter` paying for the work when big integers are involved.
+    pub(crate) fn apply(
+        self,
+        op: &Token,
+        lhs: &Int,
+        rhs: &Int,
+        meter: &mut Meter,
+    ) -> Result<Int, ErrorKind> {
+        // these don't depend on the mode
+        match op {
+            Token::Slash | Token::Percent if rhs.is_zero() => {
+                return Err(ErrorKind::DivisionByZero)
+            }
+            Token::Pow if rhs.is_negative() => return Err(ErrorKind::NegativeExponent),
+            _ => {}
+        }
+        if self == Overflow::Arbitrary {
+            return Self::apply_arbitrary(op, lhs, rhs, meter);
+        }
+
+        let (Int::Small(lhs), Int::Small(rhs)) = (self.narrow(lhs)?, self.narrow(rhs)?) else {
+            unreachable!("narrowing always yields a small integer");
+        };
+        let result = match (op, self) {
+            (Token::Plus, Overflow::Checked) => lhs.checked_add(rhs),
+            (Token::Plus, Overflow::Wrapping) => Some(lhs.wrapping_add(rhs)),
+            (Token::Plus, Overflow::Saturating) => Some(lhs.saturating_add(rhs)),
+
+            (Token::Minus, Overflow::Checked) => lhs.checked_sub(rhs),
+            (Token::Minus, Overflow::Wrapping) => Some(lhs.wrapping_sub(rhs)),
+            (Token::Minus, Overflow::Saturating) => Some(lhs.saturating_sub(rhs)),
+
+            (Token::Star, Overflow::Checked) => lhs.checked_mul(rhs),
+            (Token::Star, Overflow::Wrapping) => Some(lhs.wrapping_mul(rhs)),
+            (Token::Star, Overflow::Saturating) => Some(lhs.saturating_mul(rhs)),
+
+            // `i32::MIN / -1` is the only quotient that can overflow
+            (Token::Slash, Overflow::Checked) => lhs.checked_div(rhs),
+            (Token::Slash, Overflow::Wrapping) => Some(lhs.wrapping_div(rhs)),
+            (Token::Slash, Overflow::Saturating) => Some(lhs.saturating_div(rhs)),
+
+            // ...whereas `i32::MIN % -1` is just 0
+            (Token::Percent, _) => Some(lhs.wrapping_rem(rhs)),
+
+            (Token::Pow, Overflow::Checked) => lhs.checked_pow(rhs as u32),
+            (Token::Pow, Overflow::Wrapping) => Some(lhs.wrapping_pow(rhs as u32)),
+            (Token::Pow, Overflow::Saturating) => Some(lhs.saturating_pow(rhs as u32)),
+
+            _ => return Err(ErrorKind::InvalidOperator(op.to_string())),
+        };
+        result.map(Int::Small).ok_or(ErrorKind::Overflow)
+    }
+
+    fn apply_arbitrary(
+        op: &Token,
+        lhs: &Int,
+        rhs: &Int,
+        meter: &mut Meter,
+    ) -> Result<Int, ErrorKind> {
+        match op {
+            Token::Plus => Ok(lhs + rhs),
+            Token::Minus => Ok(lhs - rhs),
+            Token::Star => lhs.mul(rhs, meter),
+            Token::Slash => lhs.div(rhs, meter),
+            Token::Percent => lhs.rem(rhs, meter),
+            Token::Pow => match (lhs, rhs) {
+                (_, Int::Small(exponent)) => lhs.pow(*exponent as u32, meter),
+                // 0, 1 and -1 are the only bases that survive a huge exponent
+                (Int::Small(0 | 1), _) => Ok(lhs.clone()),
+                (Int::Small(-1), Int::Big(exponent)) => {
+                    Ok(Int::Small(if exponent.wrapping_to_i32() % 2 == 0 {
+                        1
+                    } else {
+                        -1
+                    }))
+                }
+                _ => Err(ErrorKind::Overflow),
+            },
+            _ => Err(ErrorKind::InvalidOperator(op.to_string())),
+        }
+    }
+}
@@ -0,0 +1,678 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+use crate::interpreter::ErrorKind;
+
+/// Paid for the limb operations of long arithmetic as they're done, so it can be
+/// stopped partway, e.g. by running out of fuel. An error aborts the operation.
+pub type Meter<'a> = dyn FnMut(u64) -> Result<(), ErrorKind> + 'a;
+
+/// A `Meter` that lets everything through, for arithmetic that isn't budgeted.
+pub fn unmetered(_: u64) -> Result<(), ErrorKind> {
+    Ok(())
+}
+
+/// An integer of any size, stored as a sign and little-endian base 2^32 limbs.
+/// The magnitude never has trailing zero limbs, and zero is never negative,
+/// so the derived `PartialEq` is numeric equality.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+/// An integer with a fast path for values that fit in an `i32`.
+/// `Big` is only ever used for values that don't, see `From<BigInt>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Int {
+    Small(i32),
+    Big(BigInt),
+}
+
+fn trim(magnitude: &mut Vec<u32>) {
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &limb) in long.iter().enumerate() {
+        let sum = limb as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry != 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+/// `a - b`, where `a >= b`
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut diff = limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = (diff < 0) as i64;
+        if diff < 0 {
+            diff += 1 << 32;
+        }
+        result.push(diff as u32);
+    }
+    trim(&mut result);
+    result
+}
+
+fn mul_magnitude(a: &[u32], b: &[u32], meter: &mut Meter) -> Result<Vec<u32>, ErrorKind> {
+    if a.is_empty() || b.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        meter(b.len() as u64)?;
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let t = x as u64 * y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = t as u32;
+            carry = t >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    trim(&mut result);
+    Ok(result)
+}
+
+/// Divides in place by a single limb, returning the remainder.
+fn div_rem_small(magnitude: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut remainder = 0u64;
+    for limb in magnitude.iter_mut().rev() {
+        let current = (remainder << 32) | *limb as u64;
+        *limb = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    trim(magnitude);
+    remainder as u32
+}
+
+/// Schoolbook binary long division; `b` must not be zero.
+fn div_rem_magnitude(
+    a: &[u32],
+    b: &[u32],
+    meter: &mut Meter,
+) -> Result<(Vec<u32>, Vec<u32>), ErrorKind> {
+    if cmp_magnitude(a, b) == Ordering::Less {
+        return Ok((Vec::new(), a.to_vec()));
+    }
+    if let [divisor] = b {
+        meter(a.len() as u64)?;
+        let mut quotient = a.to_vec();
+        let remainder = div_rem_small(&mut quotient, *divisor);
+        let mut remainder = vec![remainder];
+        trim(&mut remainder);
+        return Ok((quotient, remainder));
+    }
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = Vec::with_capacity(b.len() + 1);
+    for bit in (0..a.len() * 32).rev() {
+        if bit % 32 == 31 {
+            meter(32 * b.len() as u64)?;
+        }
+        // remainder = remainder << 1 | next bit of `a`
+        let mut carry = (a[bit / 32] >> (bit % 32)) & 1;
+        for limb in remainder.iter_mut() {
+            let next = *limb >> 31;
+            *limb = (*limb << 1) | carry;
+            carry = next;
+        }
+        if carry != 0 {
+            remainder.push(carry);
+        }
+        if cmp_magnitude(&remainder, b) != Ordering::Less {
+            remainder = sub_magnitude(&remainder, b);
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+    trim(&mut quotient);
+    Ok((quotient, remainder))
+}
+
+impl BigInt {
+    fn new(negative: bool, mut magnitude: Vec<u32>) -> Self {
+        trim(&mut magnitude);
+        BigInt {
+            negative: negative && !magnitude.is_empty(),
+            magnitude,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// Number of bits in the magnitude.
+    pub fn bits(&self) -> u64 {
+        match self.magnitude.last() {
+            Some(top) => self.magnitude.len() as u64 * 32 - top.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    pub fn to_i32(&self) -> Option<i32> {
+        match self.magnitude[..] {
+            [] => Some(0),
+            [limb] if self.negative => 0i32.checked_sub_unsigned(limb),
+            [limb] => i32::try_from(limb).ok(),
+            _ => None,
+        }
+    }
+
+    /// Nearest `f64`, or an infinity if it's too big.
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &limb| acc * 4294967296.0 + limb as f64);
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    /// The low 32 bits, as two's complement.
+    pub fn wrapping_to_i32(&self) -> i32 {
+        let low = self.magnitude.first().copied().unwrap_or(0);
+        if self.negative {
+            low.wrapping_neg() as i32
+        } else {
+            low as i32
+        }
+    }
+
+    pub fn mul(&self, rhs: &BigInt, meter: &mut Meter) -> Result<BigInt, ErrorKind> {
+        let magnitude = mul_magnitude(&self.magnitude, &rhs.magnitude, meter)?;
+        Ok(BigInt::new(self.negative != rhs.negative, magnitude))
+    }
+
+    /// Truncating division and remainder (the remainder takes the sign of `self`),
+    /// like Rust's `/` and `%`.
+    pub fn div_rem(&self, rhs: &BigInt, meter: &mut Meter) -> Result<(BigInt, BigInt), ErrorKind> {
+        if rhs.is_zero() {
+            return Err(ErrorKind::DivisionByZero);
+        }
+        let (quotient, remainder) = div_rem_magnitude(&self.magnitude, &rhs.magnitude, meter)?;
+        Ok((
+            BigInt::new(self.negative != rhs.negative, quotient),
+            BigInt::new(self.negative, remainder),
+        ))
+    }
+
+    pub fn pow(&self, mut exponent: u32, meter: &mut Meter) -> Result<BigInt, ErrorKind> {
+        let mut base = self.clone();
+        let mut result = BigInt::from(1);
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.mul(&base, meter)?;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.mul(&base, meter)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// In decimal, as `Display` has it. That takes time quadratic in the length.
+    pub fn to_decimal(&self, meter: &mut Meter) -> Result<String, ErrorKind> {
+        if self.is_zero() {
+            return Ok("0".to_string());
+        }
+        let mut magnitude = self.magnitude.clone();
+        let mut chunks = Vec::new();
+        while !magnitude.is_empty() {
+            meter(magnitude.len() as u64)?;
+            chunks.push(div_rem_small(&mut magnitude, 1_000_000_000));
+        }
+        let mut text = String::with_capacity(chunks.len() * 9 + 1);
+        if self.negative {
+            text.push('-');
+        }
+        let mut chunks = chunks.iter().rev();
+        text.push_str(&chunks.next().unwrap().to_string());
+        for chunk in chunks {
+            text.push_str(&format!("{:09}", chunk));
+        }
+        Ok(text)
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> Self {
+        let magnitude = value.unsigned_abs();
+        BigInt::new(value < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl FromStr for BigInt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("Invalid integer: `{}`", s));
+        }
+        let mut magnitude = Vec::new();
+        // 9 decimal digits always fit in a limb
+        for chunk in digits.as_bytes().chunks(9) {
+            let chunk = std::str::from_utf8(chunk).unwrap();
+            let scale = 10u64.pow(chunk.len() as u32);
+            let mut carry = chunk.parse::<u64>().unwrap();
+            for limb in magnitude.iter_mut() {
+                let t = *limb as u64 * scale + carry;
+                *limb = t as u32;
+                carry = t >> 32;
+            }
+            if carry != 0 {
+                magnitude.push(carry as u32);
+            }
+        }
+        Ok(BigInt::new(negative, magnitude))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = self.to_decimal(&mut unmetered).expect("unmetered");
+        f.write_str(&text)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.magnitude, &other.magnitude),
+            (true, true) => cmp_magnitude(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.magnitude.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, rhs: &BigInt) -> BigInt {
+        if self.negative == rhs.negative {
+            return BigInt::new(
+                self.negative,
+                add_magnitude(&self.magnitude, &rhs.magnitude),
+            );
+        }
+        match cmp_magnitude(&self.magnitude, &rhs.magnitude) {
+            Ordering::Less => {
+                BigInt::new(rhs.negative, sub_magnitude(&rhs.magnitude, &self.magnitude))
+            }
+            _ => BigInt::new(
+                self.negative,
+                sub_magnitude(&self.magnitude, &rhs.magnitude),
+            ),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, rhs: &BigInt) -> BigInt {
+        self + &-rhs
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, rhs: &BigInt) -> BigInt {
+        BigInt::mul(self, rhs, &mut unmetered).expect("unmetered")
+    }
+}
+
+impl Int {
+    /// Tries `small` when both operands are small, and falls back to `big`
+    /// if either isn't or `small` overflows.
+    fn fast_path(
+        &self,
+        rhs: &Int,
+        small: impl Fn(i32, i32) -> Option<i32>,
+        big: impl FnOnce(&BigInt, &BigInt) -> Result<BigInt, ErrorKind>,
+    ) -> Result<Int, ErrorKind> {
+        if let (Int::Small(a), Int::Small(b)) = (self, rhs) {
+            if let Some(result) = small(*a, *b) {
+                return Ok(Int::Small(result));
+            }
+        }
+        big(&self.to_big(), &rhs.to_big()).map(Int::from)
+    }
+
+    pub fn to_big(&self) -> BigInt {
+        match self {
+            Int::Small(value) => BigInt::from(*value as i64),
+            Int::Big(value) => value.clone(),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Int::Small(0))
+    }
+
+    pub fn bits(&self) -> u64 {
+        match self {
+            Int::Small(value) => 32 - value.unsigned_abs().leading_zeros() as u64,
+            Int::Big(value) => value.bits(),
+        }
+    }
+
+    /// Bytes this integer keeps on the heap.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Int::Small(_) => 0,
+            Int::Big(value) => value.magnitude.len() * std::mem::size_of::<u32>(),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Int::Small(value) => *value as f64,
+            Int::Big(value) => value.to_f64(),
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Int::Small(value) => *value < 0,
+            Int::Big(value) => value.is_negative(),
+        }
+    }
+
+    pub fn mul(&self, rhs: &Int, meter: &mut Meter) -> Result<Int, ErrorKind> {
+        self.fast_path(rhs, i32::checked_mul, |a, b| a.mul(b, meter))
+    }
+
+    /// Fails with `DivisionByZero` if `rhs` is zero.
+    pub fn div(&self, rhs: &Int, meter: &mut Meter) -> Result<Int, ErrorKind> {
+        if rhs.is_zero() {
+            return Err(ErrorKind::DivisionByZero);
+        }
+        self.fast_path(rhs, i32::checked_div, |a, b| Ok(a.div_rem(b, meter)?.0))
+    }
+
+    /// Fails with `DivisionByZero` if `rhs` is zero.
+    pub fn rem(&self, rhs: &Int, meter: &mut Meter) -> Result<Int, ErrorKind> {
+        if rhs.is_zero() {
+            return Err(ErrorKind::DivisionByZero);
+        }
+        // `i32::MIN % -1` is 0, not an overflow
+        let small = |a: i32, b: i32| Some(a.wrapping_rem(b));
+        self.fast_path(rhs, small, |a, b| Ok(a.div_rem(b, meter)?.1))
+    }
+
+    pub fn pow(&self, exponent: u32, meter: &mut Meter) -> Result<Int, ErrorKind> {
+        if let Int::Small(base) = self {
+            if let Some(result) = base.checked_pow(exponent) {
+                return Ok(Int::Small(result));
+            }
+        }
+        self.to_big().pow(exponent, meter).map(Int::from)
+    }
+
+    /// In decimal, as `Display` has it.
+    pub fn to_decimal(&self, meter: &mut Meter) -> Result<String, ErrorKind> {
+        match self {
+            Int::Small(value) => Ok(value.to_string()),
+            Int::Big(value) => value.to_decimal(meter),
+        }
+    }
+}
+
+impl From<i32> for Int {
+    fn from(value: i32) -> Self {
+        Int::Small(value)
+    }
+}
+
+impl From<BigInt> for Int {
+    fn from(value: BigInt) -> Self {
+        match value.to_i32() {
+            Some(small) => Int::Small(small),
+            None => Int::Big(value),
+        }
+    }
+}
+
+impl FromStr for Int {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<i32>() {
+            Ok(value) => Ok(Int::Small(value)),
+            Err(_) => s.parse::<BigInt>().map(Int::from),
+        }
+    }
+}
+
+impl fmt::Display for Int {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Int::Small(value) => write!(f, "{}", value),
+            Int::Big(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Ord for Int {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Int::Small(a), Int::Small(b)) => a.cmp(b),
+            _ => self.to_big().cmp(&other.to_big()),
+        }
+    }
+}
+
+impl PartialOrd for Int {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &Int {
+    type Output = Int;
+
+    fn add(self, rhs: &Int) -> Int {
+        self.fast_path(rhs, i32::checked_add, |a, b| Ok(a + b))
+            .expect("addition can't fail")
+    }
+}
+
+impl Sub for &Int {
+    type Output = Int;
+
+    fn sub(self, rhs: &Int) -> Int {
+        self.fast_path(rhs, i32::checked_sub, |a, b| Ok(a - b))
+            .expect("subtraction can't fail")
+    }
+}
+
+impl Mul for &Int {
+    type Output = Int;
+
+    fn mul(self, rhs: &Int) -> Int {
+        Int::mul(self, rhs, &mut unmetered).expect("unmetered")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    fn div_rem(a: &str, b: &str) -> (String, String) {
+        let (q, r) = big(a).div_rem(&big(b), &mut unmetered).unwrap();
+        (q.to_string(), r.to_string())
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for s in [
+            "0",
+            "1",
+            "-1",
+            "4294967295",
+            "4294967296",
+            "-18446744073709551616",
+            "1000000000",
+            "123456789012345678901234567890",
+        ] {
+            assert_eq!(big(s).to_string(), s);
+        }
+        assert_eq!(big("-0").to_string(), "0");
+        assert!(!big("-0").is_negative());
+        assert_eq!(big("000123").to_string(), "123");
+        for s in ["", "-", "1-2", "12a", "+5", " 1"] {
+            assert!(s.parse::<BigInt>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn add_and_sub() {
+        // carries across every limb
+        assert_eq!(
+            (&big("18446744073709551615") + &big("1")).to_string(),
+            "18446744073709551616"
+        );
+        assert_eq!(
+            (&big("18446744073709551616") - &big("1")).to_string(),
+            "18446744073709551615"
+        );
+        assert_eq!((&big("-5") + &big("3")).to_string(), "-2");
+        assert_eq!((&big("5") + &big("-8")).to_string(), "-3");
+        assert_eq!((&big("-5") - &big("-5")).to_string(), "0");
+        assert_eq!((&big("3") - &big("10000000000")).to_string(), "-9999999997");
+    }
+
+    #[test]
+    fn mul() {
+        assert_eq!(
+            (&big("4294967295") * &big("4294967295")).to_string(),
+            "18446744065119617025"
+        );
+        assert_eq!(
+            (&big("-123456789012345678901") * &big("987654321")).to_string(),
+            "-121932631124828532112251181221"
+        );
+        assert_eq!((&big("-7") * &big("-6")).to_string(), "42");
+        assert_eq!((&big("-7") * &big("0")).to_string(), "0");
+    }
+
+    #[test]
+    fn div_and_rem_truncate() {
+        assert_eq!(div_rem("7", "2"), ("3".into(), "1".into()));
+        assert_eq!(div_rem("-7", "2"), ("-3".into(), "-1".into()));
+        assert_eq!(div_rem("7", "-2"), ("-3".into(), "1".into()));
+        assert_eq!(div_rem("-7", "-2"), ("3".into(), "-1".into()));
+        assert_eq!(div_rem("3", "10000000000"), ("0".into(), "3".into()));
+        assert_eq!(
+            div_rem("121932631124828532112251181221", "-987654321"),
+            ("-123456789012345678901".into(), "0".into())
+        );
+        assert_eq!(
+            div_rem("100000000000000000000000000001", "18446744073709551616"),
+            ("5421010862".into(), "7886392056514347009".into())
+        );
+        assert!(matches!(
+            big("1").div_rem(&big("0"), &mut unmetered),
+            Err(ErrorKind::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn pow() {
+        let pow =
+            |base: &str, exponent| big(base).pow(exponent, &mut unmetered).unwrap().to_string();
+        assert_eq!(pow("2", 100), "1267650600228229401496703205376");
+        assert_eq!(pow("-3", 41), "-36472996377170786403");
+        assert_eq!(pow("-3", 40), "12157665459056928801");
+        assert_eq!(pow("12345", 0), "1");
+        assert_eq!(pow("0", 0), "1");
+        assert_eq!(pow("0", 7), "0");
+    }
+
+    #[test]
+    fn int_stays_small_when_it_fits() {
+        let int = |s: &str| s.parse::<Int>().unwrap();
+        assert_eq!(int("2147483647"), Int::Small(i32::MAX));
+        assert!(matches!(int("2147483648"), Int::Big(_)));
+        assert_eq!(&int("2147483648") - &int("1"), Int::Small(i32::MAX));
+        assert_eq!(&int("-2147483647") - &int("1"), Int::Small(i32::MIN));
+        assert_eq!(
+            int("-2147483648").div(&int("-1"), &mut unmetered).unwrap(),
+            int("2147483648")
+        );
+        assert_eq!(
+            int("-2147483648").rem(&int("-1"), &mut unmetered).unwrap(),
+            Int::Small(0)
+        );
+        assert_eq!(big("-4294967297").wrapping_to_i32(), -1);
+    }
+
+    #[test]
+    fn meter_stops_long_operations() {
+        let mut paid = 0;
+        let mut meter = |units| {
+            paid += units;
+            if paid > 1_000_000 {
+                Err(ErrorKind::OutOfFuel { used: paid })
+            } else {
+                Ok(())
+            }
+        };
+        assert!(matches!(
+            big("3").pow(3_000_000, &mut meter),
+            Err(ErrorKind::OutOfFuel { .. })
+        ));
+    }
+}
@@ -0,0 +1,233 @@
+use std::time::{Duration, Instant};
+
+use crate::interpreter::ErrorKind;
+
+/// What a run has consumed so far.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    /// AST nodes evaluated, plus a step per `WORK_PER_STEP` of big integer arithmetic
+    pub steps: u64,
+    pub elapsed: Duration,
+    /// Heap bytes allocated for runtime values
+    pub allocated: usize,
+    pub memory_limit: Option<usize>,
+}
+
+/// Limb operations of big integer arithmetic that cost as much fuel as a node.
+pub const WORK_PER_STEP: u64 = 4096;
+
+/// Execution limits for sandboxed runs, and what's been used of them.
+/// Every evaluated node costs one unit of fuel, as does big integer arithmetic
+/// per `WORK_PER_STEP` limb operations as it goes, and every heap allocation
+/// for a runtime value is charged against the memory limit.
+/// Values are never handed back to the budget, so the limit bounds
+/// everything a run allocates, not just what's alive at one time.
+#[derive(Debug, Clone, Default)]
+pub struct Budget {
+    pub fuel: Option<u64>,
+    pub deadline: Option<Instant>,
+    /// Like `deadline`, but counted from the first step
+    pub timeout: Option<Duration>,
+    pub memory_limit: Option<usize>,
+    steps: u64,
+    allocated: usize,
+    // limb operations not yet paid for with a step
+    work: u64,
+    // the clock starts at the first step, not when the budget is set up
+    started: Option<Instant>,
+    // and stops when the run does, see `finish`
+    finished: Option<Instant>,
+    // the earlier of `deadline` and `timeout` after the first step
+    until: Option<Instant>,
+}
+
+impl Budget {
+    /// Takes one step, or fails if that would go over a limit.
+    pub fn tick(&mut self) -> Result<(), ErrorKind> {
+        self.finished = None;
+        let started = match self.started {
+            Some(started) => started,
+            None => {
+                let now = Instant::now();
+                self.until = self.deadline;
+                if let Some(timeout) = self.timeout {
+                    let deadline = now + timeout;
+                    self.until = Some(self.until.map_or(deadline, |d| d.min(deadline)));
+                }
+                *self.started.insert(now)
+            }
+        };
+        if self.fuel.is_some_and(|fuel| self.steps >= fuel) {
+            return Err(ErrorKind::OutOfFuel { used: self.steps });
+        }
+        // only read the clock when there's a deadline, it's most of the cost of a step
+        if let Some(deadline) = self.until {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ErrorKind::Timeout {
+                    elapsed: now - started,
+                });
+            }
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// Pays for `units` limb operations of big integer arithmetic, see `bigint::Meter`.
+    pub fn work(&mut self, units: u64) -> Result<(), ErrorKind> {
+        self.work += units;
+        let steps = self.work / WORK_PER_STEP;
+        self.work %= WORK_PER_STEP;
+        self.charge_steps(steps)
+    }
+
+    /// Takes `n` steps at once, failing where `n` calls to `tick` would have.
+    pub fn charge_steps(&mut self, n: u64) -> Result<(), ErrorKind> {
+        if n == 0 {
+            return Ok(());
+        }
+        self.tick()?;
+        let rest = n - 1;
+        if let Some(fuel) = self
+            .fuel
+            .filter(|&fuel| self.steps.saturating_add(rest) > fuel)
+        {
+            self.steps = fuel;
+            return Err(ErrorKind::OutOfFuel { used: fuel });
+        }
+        self.steps += rest;
+        Ok(())
+    }
+
+    /// Accounts for `bytes` of heap, or fails if that would go over the limit.
+    /// Call it before allocating whenever the size is known up front.
+    pub fn charge(&mut self, bytes: usize) -> Result<(), ErrorKind> {
+        let allocated = self.allocated.saturating_add(bytes);
+        if let Some(limit) = self.memory_limit.filter(|&limit| allocated > limit) {
+            return Err(ErrorKind::OutOfMemory {
+                requested: bytes,
+                allocated: self.allocated,
+                limit,
+            });
+        }
+        self.allocated = allocated;
+        Ok(())
+    }
+
+    /// Forgets what earlier runs used, keeping the limits, so the next run gets all of them.
+    pub fn restart(&mut self) {
+        *self = Budget {
+            fuel: self.fuel,
+            deadline: self.deadline,
+            timeout: self.timeout,
+            memory_limit: self.memory_limit,
+            ..Budget::default()
+        };
+    }
+
+    /// Carries on from a run that had already used this much, e.g. from a snapshot.
+    pub fn resume(&mut self, steps: u64, allocated: usize) {
+        self.steps = steps;
+        self.allocated = allocated;
+    }
+
+    /// Stops the clock for `stats` at the end of a run. The next step starts it again.
+    pub fn finish(&mut self) {
+        if self.started.is_some() {
+            self.finished.get_or_insert_with(Instant::now);
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            steps: self.steps,
+            elapsed: self.started.map_or(Duration::ZERO, |started| {
+                self.finished.unwrap_or_else(Instant::now) - started
+            }),
+            allocated: self.allocated,
+            memory_limit: self.memory_limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what `tick` would have done `n` times
+    fn ticked(fuel: u64, n: u64) -> (Result<(), String>, u64) {
+        let mut budget = Budget {
+            fuel: Some(fuel),
+            ..Budget::default()
+        };
+        let result = (0..n).try_for_each(|_| budget.tick());
+        (
+            result.map_err(|kind| kind.to_string()),
+            budget.stats().steps,
+        )
+    }
+
+    fn charged(fuel: u64, n: u64) -> (Result<(), String>, u64) {
+        let mut budget = Budget {
+            fuel: Some(fuel),
+            ..Budget::default()
+        };
+        let result = budget.charge_steps(n);
+        (
+            result.map_err(|kind| kind.to_string()),
+            budget.stats().steps,
+        )
+    }
+
+    #[test]
+    fn charging_steps_is_ticking() {
+        for (fuel, n) in [
+            (10, 0),
+            (10, 1),
+            (10, 9),
+            (10, 10),
+            (10, 11),
+            (0, 1),
+            (5, 100),
+        ] {
+            assert_eq!(
+                charged(fuel, n),
+                ticked(fuel, n),
+                "fuel {}, {} steps",
+                fuel,
+                n
+            );
+        }
+        // without looping over all of them
+        let (result, steps) = charged(10, u64::MAX);
+        assert_eq!(result.unwrap_err(), "out of fuel after 10 steps");
+        assert_eq!(steps, 10);
+    }
+
+    #[test]
+    fn memory_is_limited() {
+        let mut budget = Budget {
+            memory_limit: Some(100),
+            ..Budget::default()
+        };
+        budget.charge(60).unwrap();
+        budget.charge(40).unwrap();
+        let err = budget.charge(1).unwrap_err();
+        assert!(matches!(
+            err,
+            ErrorKind::OutOfMemory {
+                requested: 1,
+                allocated: 100,
+                limit: 100
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "out of memory: 1 more bytes would go over the limit of 100 (100 in use)"
+        );
+        // a failed charge takes nothing
+        assert_eq!(budget.stats().allocated, 100);
+        assert!(budget.charge(usize::MAX).is_err());
+        budget.charge(0).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::BufRead;
+
+use crate::arith::Overflow;
+use crate::bigint::Int;
+use crate::interpreter::ErrorKind;
+use crate::value::Value;
+
+/// Calls the builtin function `name`.
+/// `input` is where `input()` reads from, `overflow` decides whether `int()` results fit.
+pub fn call(
+    name: &str,
+    args: &[Value],
+    input: &mut impl BufRead,
+    overflow: Overflow,
+) -> Result<Value, ErrorKind> {
+    match name {
+        "input" => {
+            arity(name, args, 0)?;
+            Ok(match read_line(input)? {
+                Some(line) => Value::Str(line.into()),
+                None => Value::Nil,
+            })
+        }
+        "int" => {
+            arity(name, args, 1)?;
+            to_int(&args[0]).and_then(|value| overflow.narrow(&value).map(Value::Int))
+        }
+        "float" => {
+            arity(name, args, 1)?;
+            to_float(&args[0]).map(Value::Float)
+        }
+        _ => Err(ErrorKind::UnknownFunction(name.to_string())),
+    }
+}
+
+/// Whether `name` is a builtin that can return something different for the same arguments.
+pub fn is_impure(name: &str) -> bool {
+    name == "input"
+}
+
+pub fn arity(name: &str, args: &[Value], expected: usize) -> Result<(), ErrorKind> {
+    if args.len() == expected {
+        return Ok(());
+    }
+    Err(ErrorKind::Arity {
+        name: name.to_string(),
+        expected,
+        found: args.len(),
+    })
+}
+
+/// Reads a line, without its line terminator. `None` at the end of the input.
+pub fn read_line(input: &mut impl BufRead) -> Result<Option<String>, ErrorKind> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) => Ok(None),
+        Ok(_) => {
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            Ok(Some(line))
+        }
+        Err(err) => Err(ErrorKind::Io(err.to_string())),
+    }
+}
+
+pub fn parse_int(text: &str) -> Result<Int, ErrorKind> {
+    let text = text.trim();
+    text.strip_prefix('+')
+        .unwrap_or(text)
+        .parse()
+        .map_err(|_| ErrorKind::Conversion(format!("{:?} is not an integer", text)))
+}
+
+fn to_int(value: &Value) -> Result<Int, ErrorKind> {
+    match value {
+        Value::Int(value) => Ok(value.clone()),
+        Value::Float(value) if value.is_finite() => {
+            // going through the decimal representation keeps huge floats exact
+            parse_int(&format!("{:.0}", value.trunc()))
+        }
+        Value::Float(value) => Err(ErrorKind::Conversion(format!(
+            "{:?} has no integer value",
+            value
+        ))),
+        Value::Bool(value) => Ok(Int::Small(*value as i32)),
+        Value::Str(text) => parse_int(text),
+        _ => Err(ErrorKind::Type(format!(
+            "cannot convert {} to int",
+            value.type_name()
+        ))),
+    }
+}
+
+fn to_float(value: &Value) -> Result<f64, ErrorKind> {
+    match value {
+        Value::Int(value) => Ok(value.to_f64()),
+        Value::Float(value) => Ok(*value),
+        Value::Str(text) => text
+            .trim()
+            .parse()
+            .map_err(|_| ErrorKind::Conversion(format!("{:?} is not a number", text.trim()))),
+        _ => Err(ErrorKind::Type(format!(
+            "cannot convert {} to float",
+            value.type_name()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(name: &str, arg: &Value, overflow: Overflow) -> Result<String, String> {
+        call(name, std::slice::from_ref(arg), &mut &b""[..], overflow)
+            .map(|value| value.repr())
+            .map_err(|kind| kind.to_string())
+    }
+
+    fn str(text: &str) -> Value {
+        Value::Str(text.into())
+    }
+
+    #[test]
+    fn converting_to_int() {
+        for (arg, expected) in [
+            (str(" +42\n"), Ok("42")),
+            (str("-7"), Ok("-7")),
+            (Value::Float(-2.9), Ok("-2")),
+            (Value::Bool(true), Ok("1")),
+            (
+                str("4.5"),
+                Err("conversion error: \"4.5\" is not an integer"),
+            ),
+            (str(""), Err("conversion error: \"\" is not an integer")),
+            (
+                str("12abc"),
+                Err("conversion error: \"12abc\" is not an integer"),
+            ),
+            (
+                Value::Float(f64::NAN),
+                Err("conversion error: NaN has no integer value"),
+            ),
+            (
+                Value::Float(f64::NEG_INFINITY),
+                Err("conversion error: -inf has no integer value"),
+            ),
+            (Value::Nil, Err("type error: cannot convert nil to int")),
+        ] {
+            assert_eq!(
+                convert("int", &arg, Overflow::Checked),
+                expected.map(String::from).map_err(String::from),
+                "{}",
+                arg.repr()
+            );
+        }
+    }
+
+    // what wrapping, saturating and arbitrary make of them, checked refuses them all
+    #[test]
+    fn out_of_range_ints() {
+        for (arg, expected) in [
+            (
+                Value::Float(1e10),
+                ["1410065408", "2147483647", "10000000000"],
+            ),
+            (
+                str("2147483648"),
+                ["-2147483648", "2147483647", "2147483648"],
+            ),
+            (
+                Value::Float(-2147483649.0),
+                ["2147483647", "-2147483648", "-2147483649"],
+            ),
+            (
+                Value::Float(1e30),
+                ["0", "2147483647", "1000000000000000019884624838656"],
+            ),
+        ] {
+            assert_eq!(
+                convert("int", &arg, Overflow::Checked),
+                Err("integer overflow".to_string())
+            );
+            for (overflow, expected) in Overflow::ALL[1..].iter().zip(expected) {
+                assert_eq!(
+                    convert("int", &arg, *overflow),
+                    Ok(expected.to_string()),
+                    "{} with {:?}",
+                    arg.repr(),
+                    overflow
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn converting_to_float() {
+        for (arg, expected) in [
+            (str(" 2.5 "), Ok("2.5")),
+            (Value::Int(3.into()), Ok("3.0")),
+            (str("two"), Err("conversion error: \"two\" is not a number")),
+            (str(""), Err("conversion error: \"\" is not a number")),
+            (
+                Value::Bool(true),
+                Err("type error: cannot convert bool to float"),
+            ),
+            (Value::Nil, Err("type error: cannot convert nil to float")),
+        ] {
+            assert_eq!(
+                convert("float", &arg, Overflow::Checked),
+                expected.map(String::from).map_err(String::from),
+                "{}",
+                arg.repr()
+            );
+        }
+    }
+}
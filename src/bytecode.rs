@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::arith::Overflow;
+use crate::lexer::{Span, Token};
+use crate::parser::AST;
+use crate::rrc;
+use crate::value::Value;
+use crate::Program;
+
+/// One stack machine instruction. Programs have no jumps, so they run straight through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Pushes `constants[index]`, narrowed to the overflow mode if it's an int
+    Constant(u32),
+    Nil,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+    /// Prints the top of the stack, leaving it there
+    Print,
+    /// Pops `argc` arguments and calls the builtin named by `constants[name]`
+    Call {
+        name: u32,
+        argc: u16,
+    },
+    Pop,
+    /// Ends the program with the top of the stack as its value
+    Return,
+}
+
+impl Op {
+    /// The operator an arithmetic instruction applies.
+    pub fn token(self) -> Option<Token> {
+        Some(match self {
+            Op::Add => Token::Plus,
+            Op::Subtract => Token::Minus,
+            Op::Multiply => Token::Star,
+            Op::Divide => Token::Slash,
+            Op::Remainder => Token::Percent,
+            Op::Power => Token::Pow,
+            _ => return None,
+        })
+    }
+}
+
+/// A compiled program: instructions, the source span of each one, and the constants they use.
+/// Runs on a [`Vm`](crate::Vm), and displays as a disassembly.
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub(crate) code: Vec<Op>,
+    pub(crate) spans: Vec<Span>,
+    /// How many AST nodes the interpreter would have started by each instruction.
+    /// The VM takes as many steps, so it runs out of fuel at the same point.
+    pub(crate) ticks: Vec<u32>,
+    pub(crate) constants: Vec<Value>,
+}
+
+impl Chunk {
+    pub fn compile(program: &Program) -> Result<Chunk, String> {
+        Chunk::lower(program.ast())
+    }
+
+    pub(crate) fn lower(ast: &AST) -> Result<Chunk, String> {
+        let mut lowering = Lowering::default();
+        lowering.lower(ast)?;
+        lowering.emit(Op::Return, ast.span());
+        Ok(lowering.chunk)
+    }
+
+    /// The chunk as an `.rrc` file, with the `#pragma overflow` of its source.
+    pub fn encode(&self, overflow: Option<Overflow>) -> Vec<u8> {
+        rrc::encode(self, overflow)
+    }
+
+    /// Reads and verifies an `.rrc` file, along with its `#pragma overflow`.
+    pub fn decode(bytes: &[u8]) -> Result<(Chunk, Option<Overflow>), String> {
+        rrc::decode(bytes)
+    }
+
+    /// Whether `bytes` look like an `.rrc` file rather than source.
+    pub fn is_bytecode(bytes: &[u8]) -> bool {
+        rrc::is_bytecode(bytes)
+    }
+}
+
+#[derive(Default)]
+struct Lowering {
+    chunk: Chunk,
+    names: HashMap<Arc<str>, u32>,
+    // nodes entered since the last instruction, the interpreter ticks them on the way down
+    pending: u32,
+}
+
+impl Lowering {
+    fn emit(&mut self, op: Op, span: Span) {
+        self.chunk.code.push(op);
+        self.chunk.spans.push(span);
+        self.chunk.ticks.push(std::mem::take(&mut self.pending));
+    }
+
+    fn constant(&mut self, value: Value) -> Result<u32, String> {
+        let index = u32::try_from(self.chunk.constants.len()).map_err(|_| "Too many constants")?;
+        self.chunk.constants.push(value);
+        Ok(index)
+    }
+
+    // names are called over and over, so they're only stored once
+    fn name(&mut self, name: &str) -> Result<u32, String> {
+        if let Some(&index) = self.names.get(name) {
+            return Ok(index);
+        }
+        let name: Arc<str> = name.into();
+        let index = self.constant(Value::Str(name.clone()))?;
+        self.names.insert(name, index);
+        Ok(index)
+    }
+
+    fn lower(&mut self, node: &AST) -> Result<(), String> {
+        self.pending += 1;
+        match node {
+            AST::BinOp(left, token, right, span) => {
+                self.lower(left)?;
+                self.lower(right)?;
+                let op = match token {
+                    Token::Plus => Op::Add,
+                    Token::Minus => Op::Subtract,
+                    Token::Star => Op::Multiply,
+                    Token::Slash => Op::Divide,
+                    Token::Percent => Op::Remainder,
+                    Token::Pow => Op::Power,
+                    _ => return Err(format!("Unexpected binary operator: `{}`", token)),
+                };
+                self.emit(op, *span);
+            }
+            AST::Num(value, span) => {
+                let index = self.constant(Value::Int(value.clone()))?;
+                self.emit(Op::Constant(index), *span);
+            }
+            AST::Print(expr, span) => {
+                self.lower(expr)?;
+                self.emit(Op::Print, *span);
+            }
+            AST::Call(name, args, span) => {
+                for arg in args {
+                    self.lower(arg)?;
+                }
+                let name = self.name(name)?;
+                let argc = u16::try_from(args.len())
+                    .map_err(|_| format!("Too many arguments at {}", span))?;
+                self.emit(Op::Call { name, argc }, *span);
+            }
+            AST::Block(statements, span) => {
+                if statements.is_empty() {
+                    self.emit(Op::Nil, *span);
+                }
+                for (i, statement) in statements.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(Op::Pop, statement.span());
+                    }
+                    self.lower(statement)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The disassembly: one instruction per line, with its offset and source position.
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (offset, (op, span)) in self.code.iter().zip(&self.spans).enumerate() {
+            write!(f, "{:04} {:>7}  ", offset, span.to_string())?;
+            match op {
+                Op::Constant(index) => writeln!(
+                    f,
+                    "constant {} ({})",
+                    index, self.constants[*index as usize]
+                )?,
+                Op::Call { name, argc } => writeln!(
+                    f,
+                    "call {} ({}) {}",
+                    name, self.constants[*name as usize], argc
+                )?,
+                Op::Nil => writeln!(f, "nil")?,
+                Op::Add => writeln!(f, "add")?,
+                Op::Subtract => writeln!(f, "subtract")?,
+                Op::Multiply => writeln!(f, "multiply")?,
+                Op::Divide => writeln!(f, "divide")?,
+                Op::Remainder => writeln!(f, "remainder")?,
+                Op::Power => writeln!(f, "power")?,
+                Op::Print => writeln!(f, "print")?,
+                Op::Pop => writeln!(f, "pop")?,
+                Op::Return => writeln!(f, "return")?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+// The C API, built with `--features capi` as a cdylib (see `examples/c/Makefile`).
+// `include/rickroust.h` declares it. Strings handed to C are owned by the
+// `RrInterpreter` and stay valid until its next `rr_eval` or `rr_interpreter_free`.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+
+use crate::convert::{self, FromValue, IntoValue};
+use crate::interpreter::{ErrorKind, Interpreter, Stdin};
+use crate::value::Value;
+
+pub const RR_OK: c_int = 0;
+pub const RR_ERROR: c_int = 1;
+
+/// A C function callable from Rickroll. It gets `argc` int arguments in `args`,
+/// writes its value to `result` and returns `RR_OK`, or fails with anything else.
+pub type RrNativeFn = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    args: *const i64,
+    argc: usize,
+    result: *mut i64,
+) -> c_int;
+
+/// An interpreter whose printed output is captured rather than written to stdout.
+pub struct RrInterpreter {
+    interpreter: Interpreter<Vec<u8>, Stdin>,
+    result: Option<CString>,
+    int_result: Option<i64>,
+    error: Option<CString>,
+    error_line: usize,
+    error_col: usize,
+    output: CString,
+}
+
+// C can't take strings with NULs in them, so those are dropped
+fn c_string(text: impl Into<Vec<u8>>) -> CString {
+    let mut bytes = text.into();
+    bytes.retain(|&byte| byte != 0);
+    CString::new(bytes).expect("NULs were removed")
+}
+
+#[no_mangle]
+pub extern "C" fn rr_interpreter_new() -> *mut RrInterpreter {
+    Box::into_raw(Box::new(RrInterpreter {
+        interpreter: Interpreter::with_io(Vec::new(), Stdin::default()),
+        result: None,
+        int_result: None,
+        error: None,
+        error_line: 0,
+        error_col: 0,
+        output: CString::default(),
+    }))
+}
+
+/// # Safety
+/// `interp` must come from `rr_interpreter_new` and not be used afterwards. NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn rr_interpreter_free(interp: *mut RrInterpreter) {
+    if !interp.is_null() {
+        drop(Box::from_raw(interp));
+    }
+}
+
+/// Parses and runs `source`, returning `RR_OK` or `RR_ERROR`.
+///
+/// # Safety
+/// `interp` must be a live interpreter and `source` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rr_eval(interp: *mut RrInterpreter, source: *const c_char) -> c_int {
+    let interp = &mut *interp;
+    interp.result = None;
+    interp.int_result = None;
+    interp.error = None;
+    interp.error_line = 0;
+    interp.error_col = 0;
+    interp.interpreter.output_mut().clear();
+
+    let result = match CStr::from_ptr(source).to_str() {
+        Ok(source) => match crate::parse(source) {
+            Ok(program) => interp
+                .interpreter
+                .run(&program)
+                .map_err(|err| (err.kind.to_string(), Some(err.span))),
+            Err(diagnostics) => {
+                let diagnostic = diagnostics.iter().next().expect("at least one diagnostic");
+                Err((diagnostic.message.clone(), diagnostic.span))
+            }
+        },
+        Err(_) => Err(("Source isn't valid UTF-8".to_string(), None)),
+    };
+    interp.output = c_string(interp.interpreter.output().as_slice());
+    match result {
+        Ok(value) => {
+            interp.int_result = i64::from_value(&value).ok();
+            interp.result = Some(c_string(value.to_string()));
+            RR_OK
+        }
+        Err((message, span)) => {
+            interp.error = Some(c_string(message));
+            if let Some(span) = span {
+                interp.error_line = span.line;
+                interp.error_col = span.col;
+            }
+            RR_ERROR
+        }
+    }
+}
+
+/// The value of the last successful `rr_eval`, as Rickroll prints it, or NULL.
+///
+/// # Safety
+/// `interp` must be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn rr_result(interp: *const RrInterpreter) -> *const c_char {
+    (*interp)
+        .result
+        .as_ref()
+        .map_or(std::ptr::null(), |result| result.as_ptr())
+}
+
+/// Writes the value of the last successful `rr_eval` to `out` and returns `RR_OK`
+/// if it's an int that fits, `RR_ERROR` otherwise.
+///
+/// # Safety
+/// `interp` must be a live interpreter and `out` writable.
+#[no_mangle]
+pub unsafe extern "C" fn rr_result_int(interp: *const RrInterpreter, out: *mut i64) -> c_int {
+    match (*interp).int_result {
+        Some(value) => {
+            *out = value;
+            RR_OK
+        }
+        None => RR_ERROR,
+    }
+}
+
+/// Why the last `rr_eval` failed, or NULL if it didn't.
+///
+/// # Safety
+/// `interp` must be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn rr_error_message(interp: *const RrInterpreter) -> *const c_char {
+    (*interp)
+        .error
+        .as_ref()
+        .map_or(std::ptr::null(), |error| error.as_ptr())
+}
+
+/// Line of the last error, from 1, or 0 if it isn't at any one place.
+///
+/// # Safety
+/// `interp` must be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn rr_error_line(interp: *const RrInterpreter) -> usize {
+    (*interp).error_line
+}
+
+/// Column of the last error, from 1, or 0 if it isn't at any one place.
+///
+/// # Safety
+/// `interp` must be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn rr_error_col(interp: *const RrInterpreter) -> usize {
+    (*interp).error_col
+}
+
+/// What the last `rr_eval` printed.
+///
+/// # Safety
+/// `interp` must be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn rr_output(interp: *const RrInterpreter) -> *const c_char {
+    (*interp).output.as_ptr()
+}
+
+/// Makes `function` callable from Rickroll as `name`, replacing any builtin of that name.
+/// `user_data` is passed back to it on every call.
+///
+/// # Safety
+/// `interp` must be a live interpreter, `name` a NUL-terminated string, and
+/// `user_data` valid for as long as `function` may be called.
+#[no_mangle]
+pub unsafe extern "C" fn rr_register(
+    interp: *mut RrInterpreter,
+    name: *const c_char,
+    function: RrNativeFn,
+    user_data: *mut c_void,
+) -> c_int {
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return RR_ERROR;
+    };
+    let owned = name.to_string();
+    let native = move |args: &[Value]| {
+        let args = (0..args.len())
+            .map(|index| convert::arg::<i64>(&owned, args, index))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut result = 0;
+        match function(user_data, args.as_ptr(), args.len(), &mut result) {
+            RR_OK => Ok(result.into_value()),
+            code => Err(ErrorKind::Native(format!(
+                "`{}` failed with code {}",
+                owned, code
+            ))),
+        }
+    };
+    (*interp).interpreter.register_fn(name, native);
+    RR_OK
+}
@@ -0,0 +1,381 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use rickroust::{dap, Chunk, Diagnostics, Interpreter, Overflow, Recording, Stats, Vm};
+
+const USAGE: &str = "\
+Usage: rickroust [OPTIONS] [FILE]
+       rickroust build --bytecode [-O] [--overflow=MODE] FILE [-o OUT]
+       rickroust dap
+
+Reads a program from FILE, or a single line from stdin.
+FILE can also be bytecode from `rickroust build`, which runs on the VM.
+`rickroust build --bytecode` compiles FILE to OUT (FILE with `.rrc`
+instead of its extension by default).
+`rickroust dap` serves the Debug Adapter Protocol over stdin and stdout.
+
+Options:
+  --backend=interp|vm|llvm run with the interpreter (default), the bytecode VM
+                           or the LLVM JIT
+  --overflow=MODE          checked (default), wrapping, saturating or
+                           arbitrary (big integers, not with llvm);
+                           overrides `#pragma overflow MODE` in the source
+  --fuel=STEPS             stop after evaluating STEPS nodes (not with llvm)
+  --timeout=MS             stop after MS milliseconds (not with llvm)
+  --memory-limit=BYTES     stop instead of allocating more than BYTES for values
+                           (not with llvm)
+  --trace                  log every evaluated node to stderr (interpreter only)
+  --profile                print time spent per line and function to stderr
+                           (interpreter only)
+  --profile-folded=FILE    also write the profile as folded stacks, for flamegraphs
+  --stats                  print what the run consumed to stderr
+  --record=FILE            save what input() and host functions return to FILE
+  --replay=FILE            rerun exactly as recorded in FILE, without reading input
+  -O                       fold constants and simplify arithmetic first, for
+                           the overflow mode the program runs in
+  --disassemble            print the VM bytecode instead of running it";
+
+#[derive(Clone, Copy)]
+enum Backend {
+    Interp,
+    Vm,
+    Llvm,
+}
+
+struct Options {
+    // `None` unless chosen on the command line
+    backend: Option<Backend>,
+    overflow: Option<Overflow>,
+    fuel: Option<u64>,
+    timeout: Option<Duration>,
+    memory_limit: Option<usize>,
+    trace: bool,
+    profile: bool,
+    profile_folded: Option<String>,
+    stats: bool,
+    record: Option<String>,
+    replay: Option<String>,
+    optimize: bool,
+    disassemble: bool,
+    path: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        backend: None,
+        overflow: None,
+        fuel: None,
+        timeout: None,
+        memory_limit: None,
+        trace: false,
+        profile: false,
+        profile_folded: None,
+        stats: false,
+        record: None,
+        replay: None,
+        optimize: false,
+        disassemble: false,
+        path: None,
+    };
+    for arg in args {
+        if let Some(backend) = arg.strip_prefix("--backend=") {
+            options.backend = Some(match backend {
+                "interp" => Backend::Interp,
+                "vm" => Backend::Vm,
+                "llvm" => Backend::Llvm,
+                _ => return Err(format!("Unknown backend `{}`", backend)),
+            });
+        } else if let Some(mode) = arg.strip_prefix("--overflow=") {
+            options.overflow = Some(mode.parse()?);
+        } else if let Some(fuel) = arg.strip_prefix("--fuel=") {
+            options.fuel = Some(
+                fuel.parse()
+                    .map_err(|_| format!("Invalid fuel `{}`", fuel))?,
+            );
+        } else if let Some(ms) = arg.strip_prefix("--timeout=") {
+            let ms = ms
+                .parse()
+                .map_err(|_| format!("Invalid timeout `{}`", ms))?;
+            options.timeout = Some(Duration::from_millis(ms));
+        } else if let Some(bytes) = arg.strip_prefix("--memory-limit=") {
+            let bytes = bytes
+                .parse()
+                .map_err(|_| format!("Invalid memory limit `{}`", bytes))?;
+            options.memory_limit = Some(bytes);
+        } else if arg == "--trace" {
+            options.trace = true;
+        } else if arg == "--profile" {
+            options.profile = true;
+        } else if let Some(path) = arg.strip_prefix("--profile-folded=") {
+            options.profile = true;
+            options.profile_folded = Some(path.to_string());
+        } else if arg == "--stats" {
+            options.stats = true;
+        } else if let Some(path) = arg.strip_prefix("--record=") {
+            options.record = Some(path.to_string());
+        } else if let Some(path) = arg.strip_prefix("--replay=") {
+            options.replay = Some(path.to_string());
+        } else if arg == "-O" {
+            options.optimize = true;
+        } else if arg == "--disassemble" {
+            options.disassemble = true;
+        } else if arg.starts_with('-') || options.path.is_some() {
+            return Err(format!("Unexpected argument `{}`\n\n{}", arg, USAGE));
+        } else {
+            options.path = Some(arg.clone());
+        }
+    }
+    if options.record.is_some() && options.replay.is_some() {
+        return Err("`--record` and `--replay` don't go together".to_string());
+    }
+    Ok(options)
+}
+
+/// The recording `--replay` names, if any.
+fn load_replay(options: &Options) -> Result<Option<Recording>, Box<dyn std::error::Error>> {
+    match &options.replay {
+        Some(path) => Ok(Some(Recording::decode(&std::fs::read(path)?)?)),
+        None => Ok(None),
+    }
+}
+
+/// Writes what was recorded to the `--record` file.
+fn save_recording(
+    options: &Options,
+    recording: Option<&Recording>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let (Some(path), Some(recording)) = (&options.record, recording) {
+        std::fs::write(path, recording.encode())?;
+    }
+    Ok(())
+}
+
+/// Runs a program from `rickroust build`.
+fn run_bytecode(bytes: &[u8], options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    if matches!(options.backend, Some(Backend::Interp | Backend::Llvm)) {
+        return Err("Compiled bytecode only runs on `--backend=vm`".into());
+    }
+    if options.trace || options.profile {
+        return Err("The VM backend doesn't support `--trace` or `--profile`".into());
+    }
+    let (chunk, pragma) = Chunk::decode(bytes)?;
+    if options.disassemble {
+        print!("{}", chunk);
+        return Ok(());
+    }
+    let overflow = options.overflow.or(pragma).unwrap_or_default();
+    run_vm(&chunk, None, overflow, options)
+}
+
+/// `source` is what `chunk` was compiled from, if it's at hand, for error reports.
+fn run_vm(
+    chunk: &Chunk,
+    source: Option<&str>,
+    overflow: Overflow,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut vm = Vm::new().with_overflow(overflow);
+    if let Some(fuel) = options.fuel {
+        vm = vm.with_fuel(fuel);
+    }
+    if let Some(timeout) = options.timeout {
+        vm = vm.with_timeout(timeout);
+    }
+    if let Some(bytes) = options.memory_limit {
+        vm = vm.with_memory_limit(bytes);
+    }
+    if options.record.is_some() {
+        vm = vm.with_recording();
+    }
+    if let Some(recording) = load_replay(options)? {
+        vm = vm.with_replay(recording);
+    }
+    let result = vm.run(chunk);
+    if options.stats {
+        print_stats(vm.stats());
+    }
+    save_recording(options, vm.recording())?;
+    if let Err(err) = result {
+        eprintln!("{}", err.report(source));
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn print_stats(stats: Stats) {
+    eprint!(
+        "steps: {}, time: {:.3?}, allocated: {} bytes",
+        stats.steps, stats.elapsed, stats.allocated
+    );
+    match stats.memory_limit {
+        Some(limit) => eprintln!(" of {}", limit),
+        None => eprintln!(),
+    }
+}
+
+/// Stops with the diagnostics if the source was no good.
+fn or_exit<T>(result: Result<T, Diagnostics>) -> T {
+    result.unwrap_or_else(|diagnostics| {
+        eprintln!("{}", diagnostics);
+        std::process::exit(1);
+    })
+}
+
+/// `rickroust build --bytecode FILE -o OUT`
+fn build(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut bytecode = false;
+    let mut optimize = false;
+    let mut overflow = None;
+    let mut path = None;
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--bytecode" {
+            bytecode = true;
+        } else if arg == "-O" {
+            optimize = true;
+        } else if let Some(mode) = arg.strip_prefix("--overflow=") {
+            overflow = Some(mode.parse()?);
+        } else if arg == "-o" {
+            out = Some(args.next().ok_or("`-o` needs a file name")?.clone());
+        } else if arg.starts_with('-') || path.is_some() {
+            return Err(format!("Unexpected argument `{}`\n\n{}", arg, USAGE).into());
+        } else {
+            path = Some(arg.clone());
+        }
+    }
+    if !bytecode {
+        return Err("Only `rickroust build --bytecode` is supported".into());
+    }
+    let path = path.ok_or_else(|| format!("No FILE to build\n\n{}", USAGE))?;
+    let out = out.unwrap_or_else(|| {
+        std::path::Path::new(&path)
+            .with_extension("rrc")
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let mut program = or_exit(rickroust::parse(&std::fs::read_to_string(&path)?));
+    // the command line wins over the source, as when running
+    let mut overflow = overflow.or(program.overflow());
+    if optimize {
+        // folded for one mode, so that's the one it has to run in
+        let mode = overflow.unwrap_or_default();
+        program = or_exit(program.optimize(mode));
+        overflow = Some(mode);
+    }
+    let chunk = Chunk::compile(&program)?;
+    std::fs::write(&out, chunk.encode(overflow))?;
+    Ok(())
+}
+
+pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("dap") => return Ok(dap::serve()?),
+        Some("build") => return build(&args[1..]),
+        _ => {}
+    }
+    let options = parse_args(&args)?;
+
+    let input = match &options.path {
+        Some(path) => {
+            let bytes = std::fs::read(path)?;
+            if Chunk::is_bytecode(&bytes) {
+                return run_bytecode(&bytes, &options);
+            }
+            String::from_utf8(bytes)?
+        }
+        None => {
+            // Read the input
+            print!(">>>");
+            // should this be a REPL?
+            io::stdout().flush()?;
+
+            let mut input = String::new();
+            io::stdin().read_line(&mut input)?;
+            input
+        }
+    };
+    let mut program = or_exit(rickroust::parse(&input));
+    // the command line wins over the source
+    let overflow = options.overflow.or(program.overflow()).unwrap_or_default();
+    if options.optimize {
+        program = or_exit(program.optimize(overflow));
+    }
+
+    if options.disassemble {
+        print!("{}", Chunk::compile(&program)?);
+        return Ok(());
+    }
+
+    match options.backend.unwrap_or(Backend::Interp) {
+        Backend::Llvm
+            if options.fuel.is_some()
+                || options.timeout.is_some()
+                || options.memory_limit.is_some()
+                || options.trace
+                || options.profile
+                || options.record.is_some()
+                || options.replay.is_some() =>
+        {
+            return Err("The LLVM backend doesn't support `--fuel`, `--timeout`, `--memory-limit`, `--trace`, `--profile`, `--record` or `--replay`".into());
+        }
+        #[cfg(feature = "llvm")]
+        Backend::Llvm => rickroust::compiler::run(&program, overflow, &[])?,
+        #[cfg(not(feature = "llvm"))]
+        Backend::Llvm => {
+            return Err(
+                "This build doesn't include the LLVM backend, rebuild with `--features llvm`"
+                    .into(),
+            );
+        }
+        Backend::Vm if options.trace || options.profile => {
+            return Err("The VM backend doesn't support `--trace` or `--profile`".into());
+        }
+        Backend::Vm => {
+            let chunk = Chunk::compile(&program)?;
+            run_vm(&chunk, Some(&input), overflow, &options)?;
+        }
+        Backend::Interp => {
+            let mut interpreter = Interpreter::new().with_overflow(overflow);
+            if let Some(fuel) = options.fuel {
+                interpreter = interpreter.with_fuel(fuel);
+            }
+            if let Some(timeout) = options.timeout {
+                interpreter = interpreter.with_timeout(timeout);
+            }
+            if let Some(bytes) = options.memory_limit {
+                interpreter = interpreter.with_memory_limit(bytes);
+            }
+            if options.trace {
+                interpreter = interpreter.with_trace(io::stderr());
+            }
+            if options.profile {
+                interpreter = interpreter.with_profiler();
+            }
+            if options.record.is_some() {
+                interpreter = interpreter.with_recording();
+            }
+            if let Some(recording) = load_replay(&options)? {
+                interpreter = interpreter.with_replay(recording);
+            }
+            let result = interpreter.run(&program);
+            if options.stats {
+                print_stats(interpreter.stats());
+            }
+            save_recording(&options, interpreter.recording())?;
+            if let Some(profiler) = interpreter.profiler() {
+                profiler.report(&mut io::stderr())?;
+                if let Some(path) = &options.profile_folded {
+                    profiler.write_folded(&mut std::fs::File::create(path)?)?;
+                }
+            }
+            if let Err(err) = result {
+                eprintln!("{}", err.report(Some(&input)));
+                std::process::exit(1);
+            }
+        }
+    }
+    Ok(())
+}
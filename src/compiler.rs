@@ -1,18 +1,53 @@
+use std::collections::HashMap;
+
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::execution_engine::ExecutionEngine;
+use inkwell::intrinsics::Intrinsic;
 use inkwell::module::Module;
-use inkwell::values::{BasicValueEnum, FunctionValue};
+use inkwell::targets::{InitializationConfig, Target};
+use inkwell::types::{BasicMetadataTypeEnum, FunctionType};
+use inkwell::values::{BasicMetadataValueEnum, BasicValueEnum, FunctionValue, IntValue};
+use inkwell::{AddressSpace, IntPredicate, OptimizationLevel};
 
-use crate::lexer::Token;
+use crate::arith::Overflow;
+use crate::bigint::Int;
+use crate::interpreter::{ErrorKind, RuntimeError};
+use crate::lexer::{Span, Token};
 use crate::parser::AST;
+use crate::runtime;
+use crate::Program;
 
-pub struct Compiler<'ctx> {
+pub(crate) struct Compiler<'ctx> {
     context: &'ctx Context,
     builder: &'ctx Builder<'ctx>,
     module: &'ctx Module<'ctx>,
     execution_engine: &'ctx ExecutionEngine<'ctx>,
     fn_value: Option<FunctionValue<'ctx>>,
+    overflow: Overflow,
+    // host functions by Rickroll name, with their arity
+    natives: HashMap<String, (FunctionValue<'ctx>, usize)>,
+}
+
+/// A host function for compiled code to call, given to [`run`].
+#[derive(Debug, Clone)]
+pub struct NativeSymbol {
+    name: String,
+    arity: usize,
+    address: usize,
+}
+
+impl NativeSymbol {
+    /// # Safety
+    ///
+    /// `address` must be an `extern "C" fn` taking `arity` `i32`s and returning an `i32`.
+    pub unsafe fn new(name: &str, arity: usize, address: usize) -> Self {
+        NativeSymbol {
+            name: name.to_string(),
+            arity,
+            address,
+        }
+    }
 }
 
 impl<'ctx> Compiler<'ctx> {
@@ -28,50 +63,68 @@ impl<'ctx> Compiler<'ctx> {
             module,
             execution_engine,
             fn_value: None,
+            overflow: Overflow::default(),
+            natives: HashMap::new(),
         }
     }
 
-    pub fn compile(&mut self, node: &AST) -> Option<BasicValueEnum<'ctx>> {
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Declares the external symbol for a host function callable from Rickroll as `name`,
+    /// which the JIT resolves to `address`. Compiled code only has integers,
+    /// so natives take and return `i32`s.
+    ///
+    /// # Safety
+    ///
+    /// `address` must be an `extern "C" fn` taking `arity` `i32`s and returning an `i32`.
+    pub unsafe fn declare_native(&mut self, name: &str, arity: usize, address: usize) {
+        let int_type = self.context.i32_type();
+        let params: Vec<BasicMetadataTypeEnum> = (0..arity).map(|_| int_type.into()).collect();
+        let function = self.get_or_declare_runtime(
+            &format!("rickroll_native_{}", name),
+            int_type.fn_type(&params, false),
+            address,
+        );
+        self.natives.insert(name.to_string(), (function, arity));
+    }
+
+    pub fn compile(&mut self, node: &AST) -> Result<BasicValueEnum<'ctx>, String> {
         match node {
-            AST::BinOp(left, op, right) => {
-                // should we replace `unwrap` by `?`
-                let lhs = self.compile(left).unwrap().into_int_value();
-                let rhs = self.compile(right).unwrap().into_int_value();
+            AST::BinOp(left, op, right, span) => {
+                let lhs = self.compile(left)?.into_int_value();
+                let rhs = self.compile(right)?.into_int_value();
 
                 match op {
-                    Token::Plus => Some(
-                        self.builder
-                            .build_int_add(lhs, rhs, "tmpadd")
-                            .unwrap()
-                            .into(),
-                    ),
-                    Token::Minus => Some(
-                        self.builder
-                            .build_int_sub(lhs, rhs, "tmpsub")
-                            .unwrap()
-                            .into(),
-                    ),
-                    Token::Star => Some(
-                        self.builder
-                            .build_int_mul(lhs, rhs, "tmpmul")
-                            .unwrap()
-                            .into(),
-                    ),
-                    Token::Slash => Some(
-                        self.builder
-                            .build_int_signed_div(lhs, rhs, "tmpdiv")
-                            .unwrap()
-                            .into(),
-                    ),
-                    _ => panic!("Unexpected binary operator"),
+                    Token::Plus => Ok(self.build_arith("sadd", lhs, rhs, *span).into()),
+                    Token::Minus => Ok(self.build_arith("ssub", lhs, rhs, *span).into()),
+                    Token::Star => Ok(self.build_arith("smul", lhs, rhs, *span).into()),
+                    Token::Slash => Ok(self.build_div(lhs, rhs, *span, false).into()),
+                    Token::Percent => Ok(self.build_div(lhs, rhs, *span, true).into()),
+                    Token::Pow => Ok(self.build_pow(lhs, rhs, *span).into()),
+                    _ => Err(format!("Unexpected binary operator: `{:?}`", op)),
                 }
             }
-            AST::Num(value) => {
+            AST::Num(value, span) => {
                 let int_type = self.context.i32_type();
-                Some(int_type.const_int(*value as u64, false).into())
+                let value = match self.overflow.narrow(value) {
+                    Ok(Int::Small(value)) => value,
+                    Ok(Int::Big(_)) => {
+                        return Err("The LLVM backend has no big integers".to_string())
+                    }
+                    Err(kind) => {
+                        // fail at the same point the interpreter would
+                        let always = self.context.bool_type().const_int(1, false);
+                        self.build_trap(always, kind, *span);
+                        0
+                    }
+                };
+                Ok(int_type.const_int(value as u64, true).into())
             }
-            AST::Print(expr) => {
-                let value = self.compile(expr).unwrap().into_int_value();
+            AST::Print(expr, _) => {
+                let value = self.compile(expr)?.into_int_value();
                 let printf = self.module.get_function("printf").unwrap();
                 let format_str = self.builder.build_global_string_ptr("%d\n", "format_str");
 
@@ -81,11 +134,331 @@ impl<'ctx> Compiler<'ctx> {
                         &[format_str.unwrap().as_pointer_value().into(), value.into()],
                         "printf_call",
                     )
+                    .unwrap();
+                Ok(value.into())
+            }
+            AST::Call(name, args, span) if self.natives.contains_key(name) => {
+                let (function, arity) = self.natives[name];
+                if args.len() != arity {
+                    return Err(format!(
+                        "runtime error at {}: {}",
+                        span,
+                        ErrorKind::Arity {
+                            name: name.clone(),
+                            expected: arity,
+                            found: args.len(),
+                        }
+                    ));
+                }
+                let args = args
+                    .iter()
+                    .map(|arg| self.compile(arg).map(BasicMetadataValueEnum::from))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(self
+                    .builder
+                    .build_call(function, &args, "native")
                     .unwrap()
                     .try_as_basic_value()
                     .left()
+                    .unwrap())
+            }
+            AST::Call(name, args, span) => match (name.as_str(), &args[..]) {
+                ("int", [AST::Call(input, input_args, input_span)])
+                    if input == "input" && input_args.is_empty() =>
+                {
+                    let int_type = self.context.i32_type();
+                    let read_int = self.get_or_declare_runtime(
+                        "rickroll_read_int",
+                        int_type.fn_type(&[int_type.into(); 5], false),
+                        runtime::rickroll_read_int as usize,
+                    );
+                    let mode = self.overflow.index() as usize;
+                    let args = [mode, span.line, span.col, input_span.line, input_span.col]
+                        .map(|arg| int_type.const_int(arg as u64, false).into());
+                    Ok(self
+                        .builder
+                        .build_call(read_int, &args, "read_int")
+                        .unwrap()
+                        .try_as_basic_value()
+                        .left()
+                        .unwrap())
+                }
+                // everything is an int already
+                ("int", [arg]) => self.compile(arg),
+                _ => Err(format!(
+                    "`{}` at {} isn't supported by the LLVM backend, which only has integers",
+                    name, span
+                )),
+            },
+            AST::Block(statements, _) => {
+                let mut value = self.context.i32_type().const_zero().into();
+                for statement in statements {
+                    value = self.compile(statement)?;
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    /// `+`, `-` and `*` through the `llvm.s{add,sub,mul}.with.overflow` family,
+    /// so every overflow mode sees the overflow bit.
+    fn build_arith(
+        &self,
+        name: &str,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+        span: Span,
+    ) -> IntValue<'ctx> {
+        if self.overflow == Overflow::Wrapping {
+            // plain LLVM arithmetic already wraps
+            return match name {
+                "sadd" => self.builder.build_int_add(lhs, rhs, "tmpadd"),
+                "ssub" => self.builder.build_int_sub(lhs, rhs, "tmpsub"),
+                _ => self.builder.build_int_mul(lhs, rhs, "tmpmul"),
             }
+            .unwrap();
+        }
+
+        let int_type = self.context.i32_type();
+        let intrinsic = Intrinsic::find(&format!("llvm.{}.with.overflow", name)).unwrap();
+        let function = intrinsic
+            .get_declaration(self.module, &[int_type.into()])
+            .unwrap();
+        let pair = self
+            .builder
+            .build_call(function, &[lhs.into(), rhs.into()], name)
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_struct_value();
+        let result = self
+            .builder
+            .build_extract_value(pair, 0, "result")
+            .unwrap()
+            .into_int_value();
+        let overflowed = self
+            .builder
+            .build_extract_value(pair, 1, "overflowed")
+            .unwrap()
+            .into_int_value();
+
+        if self.overflow == Overflow::Checked {
+            self.build_trap(overflowed, ErrorKind::Overflow, span);
+            return result;
+        }
+
+        // Saturating: the sign of the exact result tells which bound to clamp to.
+        // For `+` and `-` the wrapped result has the opposite sign of the exact one,
+        // for `*` the exact result is negative iff the operands' signs differ.
+        let negative = if name == "smul" {
+            let signs = self.builder.build_xor(lhs, rhs, "signs").unwrap();
+            self.build_is_negative(signs)
+        } else {
+            let wrapped_negative = self.build_is_negative(result);
+            self.builder
+                .build_not(wrapped_negative, "negative")
+                .unwrap()
+        };
+        let bound = self
+            .builder
+            .build_select(
+                negative,
+                int_type.const_int(i32::MIN as u64, true),
+                int_type.const_int(i32::MAX as u64, true),
+                "bound",
+            )
+            .unwrap();
+        self.builder
+            .build_select(overflowed, bound, result.into(), "saturated")
+            .unwrap()
+            .into_int_value()
+    }
+
+    /// `/`, or `%` if `remainder` is set
+    fn build_div(
+        &self,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+        span: Span,
+        remainder: bool,
+    ) -> IntValue<'ctx> {
+        let int_type = self.context.i32_type();
+        let is_zero = self
+            .builder
+            .build_int_compare(IntPredicate::EQ, rhs, int_type.const_zero(), "is_zero")
+            .unwrap();
+        self.build_trap(is_zero, ErrorKind::DivisionByZero, span);
+
+        // `i32::MIN / -1` is UB for `sdiv` (and `srem`), so it never reaches it
+        let is_min = self
+            .builder
+            .build_int_compare(
+                IntPredicate::EQ,
+                lhs,
+                int_type.const_int(i32::MIN as u64, true),
+                "is_min",
+            )
+            .unwrap();
+        let is_minus_one = self
+            .builder
+            .build_int_compare(
+                IntPredicate::EQ,
+                rhs,
+                int_type.const_int(-1i32 as u64, true),
+                "is_minus_one",
+            )
+            .unwrap();
+        let overflowed = self
+            .builder
+            .build_and(is_min, is_minus_one, "overflowed")
+            .unwrap();
+        if self.overflow == Overflow::Checked && !remainder {
+            self.build_trap(overflowed, ErrorKind::Overflow, span);
         }
+        let divisor = self
+            .builder
+            .build_select(overflowed, int_type.const_int(1, false), rhs, "divisor")
+            .unwrap()
+            .into_int_value();
+        if remainder {
+            // `i32::MIN % 1` is 0, which is what `i32::MIN % -1` should be
+            return self
+                .builder
+                .build_int_signed_rem(lhs, divisor, "tmprem")
+                .unwrap();
+        }
+        // `i32::MIN / 1` is already the wrapped quotient
+        let quotient = self
+            .builder
+            .build_int_signed_div(lhs, divisor, "tmpdiv")
+            .unwrap();
+        if self.overflow != Overflow::Saturating {
+            return quotient;
+        }
+        self.builder
+            .build_select(
+                overflowed,
+                int_type.const_int(i32::MAX as u64, true),
+                quotient,
+                "saturated",
+            )
+            .unwrap()
+            .into_int_value()
+    }
+
+    /// `**` through the runtime, which squares rather than multiplying `exponent` times
+    fn build_pow(
+        &self,
+        base: IntValue<'ctx>,
+        exponent: IntValue<'ctx>,
+        span: Span,
+    ) -> IntValue<'ctx> {
+        let int_type = self.context.i32_type();
+        let pow = self.get_or_declare_runtime(
+            "rickroll_pow",
+            int_type.fn_type(&[int_type.into(); 5], false),
+            runtime::rickroll_pow as usize,
+        );
+        let mode = int_type.const_int(self.overflow.index() as u64, false);
+        let [line, col] = [span.line, span.col].map(|arg| int_type.const_int(arg as u64, false));
+        self.builder
+            .build_call(
+                pow,
+                &[
+                    mode.into(),
+                    base.into(),
+                    exponent.into(),
+                    line.into(),
+                    col.into(),
+                ],
+                "pow",
+            )
+            .unwrap()
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value()
+    }
+
+    fn build_is_negative(&self, value: IntValue<'ctx>) -> IntValue<'ctx> {
+        self.builder
+            .build_int_compare(
+                IntPredicate::SLT,
+                value,
+                self.context.i32_type().const_zero(),
+                "is_negative",
+            )
+            .unwrap()
+    }
+
+    /// Reports `kind` the same way the interpreter does and exits, if `condition` holds.
+    fn build_trap(&self, condition: IntValue<'ctx>, kind: ErrorKind, span: Span) {
+        let function = self.fn_value.unwrap();
+        let trap_block = self.context.append_basic_block(function, "trap");
+        let continue_block = self.context.append_basic_block(function, "continue");
+        self.builder
+            .build_conditional_branch(condition, trap_block, continue_block)
+            .unwrap();
+
+        self.builder.position_at_end(trap_block);
+        let int_type = self.context.i32_type();
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let dprintf = self.get_or_declare(
+            "dprintf",
+            int_type.fn_type(&[int_type.into(), ptr_type.into()], true),
+        );
+        let exit = self.get_or_declare(
+            "exit",
+            self.context.void_type().fn_type(&[int_type.into()], false),
+        );
+        let format_str = self
+            .builder
+            .build_global_string_ptr("%s\n", "error_format_str")
+            .unwrap();
+        let message = self
+            .builder
+            .build_global_string_ptr(&RuntimeError::new(kind, span).report(None), "error_msg")
+            .unwrap();
+        self.builder
+            .build_call(
+                dprintf,
+                &[
+                    // stderr
+                    int_type.const_int(2, false).into(),
+                    format_str.as_pointer_value().into(),
+                    message.as_pointer_value().into(),
+                ],
+                "dprintf_call",
+            )
+            .unwrap();
+        self.builder
+            .build_call(exit, &[int_type.const_int(1, false).into()], "")
+            .unwrap();
+        self.builder.build_unreachable().unwrap();
+
+        self.builder.position_at_end(continue_block);
+    }
+
+    fn get_or_declare(&self, name: &str, fn_type: FunctionType<'ctx>) -> FunctionValue<'ctx> {
+        self.module
+            .get_function(name)
+            .unwrap_or_else(|| self.module.add_function(name, fn_type, None))
+    }
+
+    /// Declares one of the functions in `runtime`, which lives at `address`.
+    fn get_or_declare_runtime(
+        &self,
+        name: &str,
+        fn_type: FunctionType<'ctx>,
+        address: usize,
+    ) -> FunctionValue<'ctx> {
+        if let Some(function) = self.module.get_function(name) {
+            return function;
+        }
+        let function = self.module.add_function(name, fn_type, None);
+        self.execution_engine.add_global_mapping(&function, address);
+        function
     }
 
     pub fn create_main_function(&mut self) {
@@ -100,6 +473,46 @@ impl<'ctx> Compiler<'ctx> {
 
     pub fn finish_main_function(&self) {
         self.builder
-            .build_return(Some(&self.context.i32_type().const_int(0, false)));
+            .build_return(Some(&self.context.i32_type().const_int(0, false)))
+            .unwrap();
+    }
+}
+
+/// Compiles `program` to LLVM IR and runs it through the JIT, with `natives`
+/// callable from it like the host functions of an `Interpreter`.
+pub fn run(program: &Program, overflow: Overflow, natives: &[NativeSymbol]) -> Result<(), String> {
+    if overflow == Overflow::Arbitrary {
+        return Err(
+            "The LLVM backend has no big integers, `arbitrary` overflow needs the interpreter"
+                .to_string(),
+        );
+    }
+    Target::initialize_native(&InitializationConfig::default())?;
+
+    let context = Context::create();
+    let module = context.create_module("rickroll");
+    let builder = context.create_builder();
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .map_err(|e| e.to_string())?;
+
+    // Add the printf function declaration to the module
+    let int_type = context.i32_type();
+    let printf_type = int_type.fn_type(&[context.ptr_type(AddressSpace::default()).into()], true);
+    module.add_function("printf", printf_type, None);
+
+    let mut compiler =
+        Compiler::new(&context, &builder, &module, &execution_engine).with_overflow(overflow);
+    for native in natives {
+        // `NativeSymbol::new` vouched for the address
+        unsafe { compiler.declare_native(&native.name, native.arity, native.address) };
+    }
+    compiler.create_main_function();
+    compiler.compile(program.ast())?;
+    compiler.finish_main_function();
+
+    unsafe {
+        execution_engine.run_function_as_main(module.get_function("main").unwrap(), &[]);
     }
+    Ok(())
 }
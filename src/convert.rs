@@ -0,0 +1,384 @@
+// Conversions between Rust types and `Value`, so hosts can register natives
+// with plain Rust signatures and read results back as Rust types.
+
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::bigint::Int;
+use crate::interpreter::ErrorKind;
+use crate::natives::NativeFn;
+use crate::value::Value;
+
+/// Rust types that can be read out of a `Value`.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, ErrorKind>;
+}
+
+/// Rust types that can be turned into a `Value`.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// What natives can return: any `IntoValue`, or a `Result` of one to fail with.
+pub trait IntoNativeResult {
+    fn into_native_result(self) -> Result<Value, ErrorKind>;
+}
+
+impl<T: IntoValue> IntoNativeResult for T {
+    fn into_native_result(self) -> Result<Value, ErrorKind> {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue> IntoNativeResult for Result<T, ErrorKind> {
+    fn into_native_result(self) -> Result<Value, ErrorKind> {
+        self.map(IntoValue::into_value)
+    }
+}
+
+/// Functions `register_fn` accepts: closures over `FromValue` arguments
+/// returning an `IntoNativeResult`, or raw `Fn(&[Value]) -> Result<Value, ErrorKind>`.
+/// `Args` only tells the two apart.
+pub trait IntoNative<Args> {
+    fn into_native(self, name: &str) -> NativeFn;
+}
+
+/// `Args` for raw natives, which check their own arguments.
+pub struct RawArgs;
+
+impl<F> IntoNative<RawArgs> for F
+where
+    F: Fn(&[Value]) -> Result<Value, ErrorKind> + 'static,
+{
+    fn into_native(self, _name: &str) -> NativeFn {
+        Rc::new(self)
+    }
+}
+
+fn mismatch(expected: &str, found: &Value) -> ErrorKind {
+    ErrorKind::Type(format!(
+        "expected {}, found {}",
+        expected,
+        found.type_name()
+    ))
+}
+
+/// Reads argument `index`, saying which argument of which function it was if that fails.
+pub(crate) fn arg<T: FromValue>(name: &str, args: &[Value], index: usize) -> Result<T, ErrorKind> {
+    T::from_value(&args[index]).map_err(|kind| match kind {
+        ErrorKind::Type(message) => {
+            ErrorKind::Type(format!("argument {} of `{}`: {}", index + 1, name, message))
+        }
+        ErrorKind::Conversion(message) => {
+            ErrorKind::Conversion(format!("argument {} of `{}`: {}", index + 1, name, message))
+        }
+        kind => kind,
+    })
+}
+
+macro_rules! impl_into_native {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg),*> IntoNative<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: IntoNativeResult,
+            $($arg: FromValue,)*
+        {
+            #[allow(non_snake_case, unused_variables, unused_mut, unused_assignments)]
+            fn into_native(self, name: &str) -> NativeFn {
+                let name = name.to_string();
+                const ARITY: usize = <[&str]>::len(&[$(stringify!($arg)),*]);
+                Rc::new(move |args: &[Value]| {
+                    crate::natives::expect_args(&name, args, ARITY)?;
+                    let mut index = 0;
+                    $(
+                        let $arg = arg::<$arg>(&name, args, index)?;
+                        index += 1;
+                    )*
+                    self($($arg),*).into_native_result()
+                })
+            }
+        }
+    };
+}
+
+impl_into_native!();
+impl_into_native!(A);
+impl_into_native!(A, B);
+impl_into_native!(A, B, C);
+impl_into_native!(A, B, C, D);
+impl_into_native!(A, B, C, D, E);
+impl_into_native!(A, B, C, D, E, G);
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, ErrorKind> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Nil
+    }
+}
+
+macro_rules! impl_int {
+    ($($ty:ty),*) => {$(
+        impl FromValue for $ty {
+            fn from_value(value: &Value) -> Result<Self, ErrorKind> {
+                let Value::Int(int) = value else {
+                    return Err(mismatch("int", value));
+                };
+                let converted = match int {
+                    Int::Small(small) => <$ty>::try_from(*small).ok(),
+                    Int::Big(_) => int.to_string().parse().ok(),
+                };
+                converted.ok_or_else(|| {
+                    ErrorKind::Conversion(format!("{} doesn't fit in {}", int, stringify!($ty)))
+                })
+            }
+        }
+
+        impl IntoValue for $ty {
+            fn into_value(self) -> Value {
+                Value::Int(match i32::try_from(self) {
+                    Ok(small) => Int::Small(small),
+                    Err(_) => self.to_string().parse().expect("integers print as integers"),
+                })
+            }
+        }
+    )*};
+}
+
+impl_int!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, ErrorKind> {
+        match value {
+            Value::Float(value) => Ok(*value),
+            // ints mix with floats everywhere else too
+            Value::Int(value) => Ok(value.to_f64()),
+            _ => Err(mismatch("float", value)),
+        }
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(value: &Value) -> Result<Self, ErrorKind> {
+        f64::from_value(value).map(|value| value as f32)
+    }
+}
+
+impl IntoValue for f32 {
+    fn into_value(self) -> Value {
+        Value::Float(self.into())
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, ErrorKind> {
+        match value {
+            Value::Bool(value) => Ok(*value),
+            _ => Err(mismatch("bool", value)),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, ErrorKind> {
+        match value {
+            Value::Str(text) => Ok(text.to_string()),
+            _ => Err(mismatch("string", value)),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, ErrorKind> {
+        match value {
+            Value::List(values) => values.iter().map(T::from_value).collect(),
+            _ => Err(mismatch("list", value)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::List(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+/// `None` is nil.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, ErrorKind> {
+        match value {
+            Value::Nil => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map_or(Value::Nil, IntoValue::into_value)
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(value: &Value) -> Result<Self, ErrorKind> {
+        match value {
+            Value::Map(entries) => entries
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), T::from_value(value)?)))
+                .collect(),
+            _ => Err(mismatch("map", value)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn into_value(self) -> Value {
+        let entries: BTreeMap<_, _> = self
+            .into_iter()
+            .map(|(key, value)| (key, value.into_value()))
+            .collect();
+        Value::Map(Arc::new(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: FromValue + IntoValue + Clone + PartialEq + std::fmt::Debug>(value: T) {
+        let converted = value.clone().into_value();
+        assert_eq!(
+            T::from_value(&converted).unwrap(),
+            value,
+            "{}",
+            converted.repr()
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        for value in [0, -1, i32::MIN, i32::MAX] {
+            round_trip(value);
+        }
+        for value in [i64::MIN, i64::MAX, i32::MAX as i64 + 1] {
+            round_trip(value);
+        }
+        round_trip(u128::MAX);
+        round_trip(i128::MIN);
+        round_trip(u8::MAX);
+        round_trip(-2.5);
+        round_trip(true);
+        round_trip("never gonna".to_string());
+        round_trip(vec![1i64, 2, 3]);
+        round_trip(vec![Some("give".to_string()), None]);
+        round_trip(None::<i64>);
+        round_trip(HashMap::from([("you".to_string(), vec![1.5])]));
+        round_trip(Value::Function("up".into()));
+        assert_eq!(u64::MAX.into_value().to_string(), "18446744073709551615");
+        assert_eq!(().into_value(), Value::Nil);
+        assert_eq!(f64::from_value(&7.into_value()).unwrap(), 7.0);
+    }
+
+    #[test]
+    fn conversion_errors() {
+        fn error<T: FromValue>(value: impl IntoValue) -> String {
+            match T::from_value(&value.into_value()) {
+                Ok(_) => "converted".to_string(),
+                Err(kind) => kind.to_string(),
+            }
+        }
+        assert_eq!(error::<i64>(1.0), "type error: expected int, found float");
+        assert_eq!(error::<u8>(256), "conversion error: 256 doesn't fit in u8");
+        assert_eq!(error::<u32>(-1), "conversion error: -1 doesn't fit in u32");
+        assert_eq!(
+            error::<i64>(u64::MAX),
+            "conversion error: 18446744073709551615 doesn't fit in i64"
+        );
+        assert_eq!(error::<bool>(()), "type error: expected bool, found nil");
+        assert_eq!(error::<String>(1), "type error: expected string, found int");
+        assert_eq!(
+            error::<Vec<i64>>(vec!["a"]),
+            "type error: expected int, found string"
+        );
+    }
+
+    #[test]
+    fn typed_natives_check_their_arguments() {
+        let add = (|a: i64, b: u8| a + b as i64).into_native("add");
+        let call = |args: &[Value]| {
+            add(args)
+                .map(|value| value.repr())
+                .map_err(|kind| kind.to_string())
+        };
+        assert_eq!(call(&[1.into_value(), 2.into_value()]), Ok("3".into()));
+        for (args, expected) in [
+            (
+                vec![1.into_value(), "2".into_value()],
+                "type error: argument 2 of `add`: expected int, found string",
+            ),
+            (
+                vec![Value::Nil, 2.into_value()],
+                "type error: argument 1 of `add`: expected int, found nil",
+            ),
+            (
+                vec![1.into_value(), 300.into_value()],
+                "conversion error: argument 2 of `add`: 300 doesn't fit in u8",
+            ),
+            (
+                vec![1.into_value()],
+                "`add` takes 2 argument(s), but 1 were given",
+            ),
+        ] {
+            assert_eq!(call(&args), Err(expected.to_string()));
+        }
+    }
+
+    #[test]
+    fn wrong_arguments_are_reported_at_the_call() {
+        let program = crate::parse("print add(1, 2)\nprint add(1, input())").unwrap();
+        let mut interpreter = crate::Interpreter::with_io(Vec::new(), &b"2\n"[..]);
+        interpreter.register_fn("add", |a: i64, b: i64| a + b);
+        let err = interpreter.run(&program).unwrap_err();
+        assert_eq!(interpreter.output(), b"3\n");
+        assert_eq!(
+            err.to_string(),
+            "runtime error at 2:7: type error: argument 2 of `add`: expected int, found string"
+        );
+    }
+}
@@ -0,0 +1,683 @@
+// `rickroust dap`: a Debug Adapter Protocol server over stdin/stdout.
+//
+// Programs are a flat list of statements with no verses or variables yet, so
+// execution pauses between top-level statements, there's a single `<main>`
+// frame, and the only variable is `_`, the value of the last statement.
+// Step in and step over both go to the next statement, step out runs
+// to the next breakpoint or the end, and can't be paused. Stdin carries the protocol, so
+// `input()` reads from the file given as `stdin` in the launch arguments.
+// Expressions from `evaluate` and `setVariable` run on their own, reading no input.
+// Launched with a `replay` recording instead, the run is replayed, and
+// step back and reverse continue go back through it.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+use crate::arith::Overflow;
+use crate::interpreter::{Interpreter, Status};
+use crate::json::{json_object, Json};
+use crate::lexer::Span;
+use crate::parser::AST;
+use crate::replay::Recording;
+use crate::value::Value;
+
+const THREAD_ID: i32 = 1;
+const LOCALS_REFERENCE: i32 = 1;
+/// The longest message read, so a bad `Content-Length` can't allocate everything
+const MAX_MESSAGE: usize = 16 << 20;
+
+/// Serves one debug session over stdin and stdout.
+pub fn serve() -> io::Result<()> {
+    serve_on(io::stdin().lock(), io::stdout().lock())
+}
+
+fn serve_on(mut input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut server = Server {
+        out: Sender {
+            writer: output,
+            seq: 0,
+        },
+        session: None,
+    };
+    while let Some(message) = read_message(&mut input)? {
+        let request = match Json::parse(&message) {
+            Ok(request) => request,
+            Err(err) => {
+                eprintln!("dap: ignoring malformed message: {}", err);
+                continue;
+            }
+        };
+        if !server.handle(&request)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads one `Content-Length`-framed message, `None` at end of input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing `Content-Length` header",
+        )
+    })?;
+    if length > MAX_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is too long", length),
+        ));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+struct Sender<W: Write> {
+    writer: W,
+    seq: i32,
+}
+
+impl<W: Write> Sender<W> {
+    fn send(&mut self, mut message: Vec<(String, Json)>, body: Json) -> io::Result<()> {
+        self.seq += 1;
+        message.insert(0, ("seq".to_string(), self.seq.into()));
+        if body != Json::Null {
+            message.push(("body".to_string(), body));
+        }
+        let message = Json::Object(message).to_string();
+        write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{}",
+            message.len(),
+            message
+        )?;
+        self.writer.flush()
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) -> io::Result<()> {
+        let mut message = vec![
+            ("type".to_string(), "response".into()),
+            (
+                "request_seq".to_string(),
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("success".to_string(), result.is_ok().into()),
+            (
+                "command".to_string(),
+                request.get("command").cloned().unwrap_or(Json::Null),
+            ),
+        ];
+        let body = match result {
+            Ok(body) => body,
+            Err(err) => {
+                message.push(("message".to_string(), err.into()));
+                Json::Null
+            }
+        };
+        self.send(message, body)
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        let message = vec![
+            ("type".to_string(), "event".into()),
+            ("event".to_string(), event.into()),
+        ];
+        self.send(message, body)
+    }
+
+    fn output(&mut self, category: &str, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.event(
+            "output",
+            json_object! { "category" => category, "output" => text },
+        )
+    }
+
+    fn stopped(&mut self, reason: &str) -> io::Result<()> {
+        self.event(
+            "stopped",
+            json_object! {
+                "reason" => reason,
+                "threadId" => THREAD_ID,
+                "allThreadsStopped" => true,
+            },
+        )
+    }
+
+    fn finished(&mut self, exit_code: i32) -> io::Result<()> {
+        self.event("exited", json_object! { "exitCode" => exit_code })?;
+        self.event("terminated", Json::Null)
+    }
+}
+
+/// A launched program, paused before the statement at `position`.
+struct Session {
+    path: String,
+    // lines that have a statement to stop at
+    lines: BTreeSet<usize>,
+    position: Option<Span>,
+    interpreter: Interpreter<Vec<u8>, Box<dyn BufRead>>,
+    overflow: Overflow,
+    last: Value,
+    breakpoints: BTreeSet<usize>,
+    stop_on_entry: bool,
+    replaying: bool,
+}
+
+impl Session {
+    fn launch(args: &Json) -> Result<Self, String> {
+        let path = args
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or("Missing `program` launch argument")?;
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Can't read `{}`: {}", path, err))?;
+        let program = crate::parse(&source).map_err(|diagnostics| diagnostics.to_string())?;
+        let overflow = match args.get("overflow").and_then(Json::as_str) {
+            Some(mode) => mode.parse()?,
+            None => program.overflow().unwrap_or_default(),
+        };
+        let lines = match program.ast() {
+            AST::Block(statements, _) => statements
+                .iter()
+                .map(|statement| statement.span().line)
+                .collect(),
+            node => BTreeSet::from([node.span().line]),
+        };
+        let input: Box<dyn BufRead> = match args.get("stdin").and_then(Json::as_str) {
+            Some(path) => Box::new(BufReader::new(
+                File::open(path).map_err(|err| format!("Can't open `{}`: {}", path, err))?,
+            )),
+            None => Box::new(io::empty()),
+        };
+        let mut interpreter = Interpreter::with_io(Vec::new(), input).with_overflow(overflow);
+        let replay = args.get("replay").and_then(Json::as_str);
+        if let Some(path) = replay {
+            let bytes =
+                std::fs::read(path).map_err(|err| format!("Can't read `{}`: {}", path, err))?;
+            interpreter = interpreter.with_replay(Recording::decode(&bytes)?);
+        }
+        let position = match interpreter.load(&program) {
+            Status::Paused { span, .. } => Some(span),
+            Status::Finished(_) | Status::Failed(_) => None,
+        };
+        Ok(Session {
+            path: path.to_string(),
+            lines,
+            position,
+            interpreter,
+            overflow,
+            last: Value::Nil,
+            breakpoints: BTreeSet::new(),
+            stop_on_entry: args
+                .get("stopOnEntry")
+                .and_then(Json::as_bool)
+                .unwrap_or(false),
+            replaying: replay.is_some(),
+        })
+    }
+
+    /// Evaluates a snippet of Rickroll with the program's overflow mode. It gets an
+    /// interpreter of its own, so it can't read the program's input or replay log.
+    fn evaluate(&mut self, expression: &str) -> Result<Value, String> {
+        let program = crate::parse(expression).map_err(|diagnostics| diagnostics.to_string())?;
+        let mut interpreter =
+            Interpreter::with_io(Vec::new(), io::empty()).with_overflow(self.overflow);
+        let result = interpreter.run(&program);
+        // printed output goes out with the program's
+        let output = interpreter.into_output();
+        self.interpreter.output_mut().extend(output);
+        result.map_err(|err| err.to_string())
+    }
+
+    fn take_output(&mut self) -> String {
+        let output = std::mem::take(self.interpreter.output_mut());
+        String::from_utf8_lossy(&output).into_owned()
+    }
+}
+
+struct Server<W: Write> {
+    out: Sender<W>,
+    session: Option<Session>,
+}
+
+impl<W: Write> Server<W> {
+    /// Answers one request, `false` once the client disconnects.
+    fn handle(&mut self, request: &Json) -> io::Result<bool> {
+        let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+        let args = request.get("arguments").unwrap_or(&Json::Null);
+        let result = self.dispatch(command, args);
+        let ok = result.is_ok();
+        self.out.respond(request, result)?;
+        if !ok {
+            return Ok(true);
+        }
+        match command {
+            "initialize" => self.out.event("initialized", Json::Null)?,
+            "configurationDone" => self.start()?,
+            "continue" | "stepOut" => self.resume(false)?,
+            "next" | "stepIn" => self.resume(true)?,
+            "stepBack" => self.reverse(true)?,
+            "reverseContinue" => self.reverse(false)?,
+            "evaluate" | "setVariable" => self.flush_output()?,
+            "disconnect" | "terminate" => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn dispatch(&mut self, command: &str, args: &Json) -> Result<Json, String> {
+        if command == "initialize" {
+            return Ok(json_object! {
+                "supportsConfigurationDoneRequest" => true,
+                "supportsSetVariable" => true,
+                "supportsStepBack" => true,
+                "supportsEvaluateForHovers" => false,
+            });
+        }
+        if command == "launch" {
+            self.session = Some(Session::launch(args)?);
+            return Ok(Json::Null);
+        }
+        if matches!(command, "disconnect" | "terminate") {
+            return Ok(Json::Null);
+        }
+        let session = self
+            .session
+            .as_mut()
+            .ok_or("No program has been launched")?;
+        match command {
+            "setBreakpoints" => {
+                let lines: Vec<usize> = args
+                    .get("breakpoints")
+                    .and_then(Json::as_array)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|breakpoint| breakpoint.get("line")?.as_i64())
+                    .map(|line| line as usize)
+                    .collect();
+                session.breakpoints = lines.iter().copied().collect();
+                let breakpoints = lines
+                    .iter()
+                    .map(|&line| {
+                        let verified = session.lines.contains(&line);
+                        json_object! { "verified" => verified, "line" => line }
+                    })
+                    .collect::<Vec<_>>();
+                Ok(json_object! { "breakpoints" => breakpoints })
+            }
+            "continue" => Ok(json_object! { "allThreadsContinued" => true }),
+            "stepBack" | "reverseContinue" if !session.replaying => {
+                Err("Stepping back needs a `replay` recording in the launch arguments".to_string())
+            }
+            "stepBack" | "reverseContinue" => Ok(Json::Null),
+            "configurationDone" | "next" | "stepIn" | "stepOut" => Ok(Json::Null),
+            // requests are only read once the program has stopped, so there's never
+            // a running program to pause
+            "pause" => Err("The program can only be paused at breakpoints".to_string()),
+            "threads" => Ok(json_object! {
+                "threads" => vec![json_object! { "id" => THREAD_ID, "name" => "main" }],
+            }),
+            "stackTrace" => {
+                let frames = match session.position {
+                    Some(span) => vec![json_object! {
+                        "id" => 0,
+                        "name" => "<main>",
+                        "line" => span.line,
+                        "column" => span.col,
+                        "source" => json_object! { "path" => session.path.as_str() },
+                    }],
+                    None => Vec::new(),
+                };
+                Ok(json_object! { "totalFrames" => frames.len(), "stackFrames" => frames })
+            }
+            "scopes" => Ok(json_object! {
+                "scopes" => vec![json_object! {
+                    "name" => "Locals",
+                    "variablesReference" => LOCALS_REFERENCE,
+                    "expensive" => false,
+                }],
+            }),
+            "variables" => Ok(json_object! {
+                "variables" => vec![json_object! {
+                    "name" => "_",
+                    "value" => session.last.repr(),
+                    "type" => session.last.type_name(),
+                    "variablesReference" => 0,
+                }],
+            }),
+            "setVariable" => {
+                let name = args.get("name").and_then(Json::as_str).unwrap_or("");
+                if name != "_" {
+                    return Err(format!("Unknown variable `{}`", name));
+                }
+                let value = args
+                    .get("value")
+                    .and_then(Json::as_str)
+                    .ok_or("Missing `value` argument")?;
+                session.last = session.evaluate(value)?;
+                session.interpreter.set_last_value(session.last.clone());
+                Ok(json_object! {
+                    "value" => session.last.repr(),
+                    "type" => session.last.type_name(),
+                    "variablesReference" => 0,
+                })
+            }
+            "evaluate" => {
+                let expression = args
+                    .get("expression")
+                    .and_then(Json::as_str)
+                    .ok_or("Missing `expression` argument")?;
+                // `_` on its own isn't an expression, but it's what you'd hover or watch
+                let value = if expression.trim() == "_" {
+                    session.last.clone()
+                } else {
+                    session.evaluate(expression)?
+                };
+                Ok(json_object! { "result" => value.repr(), "variablesReference" => 0 })
+            }
+            _ => Err(format!("Unsupported request `{}`", command)),
+        }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        let Some(session) = &self.session else {
+            return Ok(());
+        };
+        if session.stop_on_entry {
+            return self.out.stopped("entry");
+        }
+        if session
+            .position
+            .is_some_and(|span| session.breakpoints.contains(&span.line))
+        {
+            return self.out.stopped("breakpoint");
+        }
+        self.resume(false)
+    }
+
+    /// Runs one statement if `step`, or until the next breakpoint.
+    fn resume(&mut self, step: bool) -> io::Result<()> {
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        // stepped one statement at a time, rather than with `run_until`,
+        // so output reaches the client as it's printed
+        loop {
+            let line = session.position.map(|span| span.line);
+            let result = session.interpreter.step();
+            let output = session.take_output();
+            self.out.output("stdout", &output)?;
+            let span = match result {
+                Ok(Status::Paused { span, .. }) => span,
+                Ok(Status::Finished(value)) => {
+                    session.last = value;
+                    session.position = None;
+                    return self.out.finished(0);
+                }
+                Ok(Status::Failed(err)) | Err(err) => {
+                    session.position = None;
+                    self.out.output("stderr", &format!("{}\n", err))?;
+                    return self.out.finished(1);
+                }
+            };
+            session.position = Some(span);
+            if let Some(value) = session.interpreter.last_value() {
+                session.last = value.clone();
+            }
+            if step {
+                return self.out.stopped("step");
+            }
+            // statements sharing a line only stop at the first one
+            if Some(span.line) != line && session.breakpoints.contains(&span.line) {
+                return self.out.stopped("breakpoint");
+            }
+        }
+    }
+
+    /// Goes back one statement if `step`, or back to the previous breakpoint or the start.
+    fn reverse(&mut self, step: bool) -> io::Result<()> {
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        loop {
+            if !session.interpreter.can_step_back() {
+                return self.out.stopped("entry");
+            }
+            let line = session.position.map(|span| span.line);
+            if let Status::Paused { span, .. } = session.interpreter.step_back() {
+                session.position = Some(span);
+            }
+            session.last = session
+                .interpreter
+                .last_value()
+                .cloned()
+                .unwrap_or(Value::Nil);
+            if step {
+                return self.out.stopped("step");
+            }
+            let span = session.position.expect("stepped back to a statement");
+            if Some(span.line) != line && session.breakpoints.contains(&span.line) {
+                return self.out.stopped("breakpoint");
+            }
+        }
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        if let Some(session) = &mut self.session {
+            let output = session.take_output();
+            self.out.output("stdout", &output)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(messages: &[Json]) -> Vec<u8> {
+        let mut framed = Vec::new();
+        for message in messages {
+            let message = message.to_string();
+            write!(
+                framed,
+                "Content-Length: {}\r\n\r\n{}",
+                message.len(),
+                message
+            )
+            .unwrap();
+        }
+        framed
+    }
+
+    fn request(seq: i32, command: &str, arguments: Json) -> Json {
+        json_object! {
+            "seq" => seq,
+            "type" => "request",
+            "command" => command,
+            "arguments" => arguments,
+        }
+    }
+
+    /// Each response and event, with the parts that tell them apart.
+    fn transcript(output: &[u8]) -> Vec<String> {
+        let mut output = output;
+        let mut lines = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            let message = Json::parse(&message).unwrap();
+            let body = message.get("body").map_or(String::new(), Json::to_string);
+            let line = match message.get("type").and_then(Json::as_str) {
+                Some("event") => format!("{} {}", message.get("event").unwrap(), body),
+                _ if message.get("success") == Some(&Json::Bool(true)) => {
+                    format!("{} ok {}", message.get("command").unwrap(), body)
+                }
+                _ => format!(
+                    "{} failed {}",
+                    message.get("command").unwrap(),
+                    message.get("message").unwrap()
+                ),
+            };
+            lines.push(line.trim_end().to_string());
+        }
+        lines
+    }
+
+    #[test]
+    fn session() {
+        let dir = std::env::temp_dir().join(format!("rickroust-dap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("program.rr");
+        let stdin = dir.join("stdin");
+        std::fs::write(&program, "print 1 + 2\nprint int(input()) * 2\nprint 5").unwrap();
+        std::fs::write(&stdin, "21\n").unwrap();
+        let path = program.to_str().unwrap();
+        let requests = [
+            request(1, "initialize", Json::Null),
+            request(
+                2,
+                "launch",
+                json_object! { "program" => path, "stdin" => stdin.to_str().unwrap() },
+            ),
+            request(
+                3,
+                "setBreakpoints",
+                json_object! {
+                    "breakpoints" => vec![json_object! { "line" => 2 }, json_object! { "line" => 9 }],
+                },
+            ),
+            request(4, "configurationDone", Json::Null),
+            request(5, "stackTrace", Json::Null),
+            request(6, "variables", Json::Null),
+            request(
+                7,
+                "evaluate",
+                json_object! { "expression" => "int(input())" },
+            ),
+            request(
+                8,
+                "evaluate",
+                json_object! { "expression" => "print 2 ** 10" },
+            ),
+            request(9, "next", Json::Null),
+            request(10, "variables", Json::Null),
+            request(
+                11,
+                "setVariable",
+                json_object! { "name" => "_", "value" => "40" },
+            ),
+            request(12, "continue", Json::Null),
+            request(13, "disconnect", Json::Null),
+            request(14, "threads", Json::Null),
+        ];
+        let mut output = Vec::new();
+        serve_on(&frame(&requests)[..], &mut output).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // nothing is read after `disconnect`
+        let expected = [
+            r#""initialize" ok {"supportsConfigurationDoneRequest":true,"supportsSetVariable":true,"supportsStepBack":true,"supportsEvaluateForHovers":false}"#,
+            r#""initialized""#,
+            r#""launch" ok"#,
+            r#""setBreakpoints" ok {"breakpoints":[{"verified":true,"line":2},{"verified":false,"line":9}]}"#,
+            r#""configurationDone" ok"#,
+            r#""output" {"category":"stdout","output":"3\n"}"#,
+            r#""stopped" {"reason":"breakpoint","threadId":1,"allThreadsStopped":true}"#,
+            r#""stackTrace" ok {"totalFrames":1,"stackFrames":[{"id":0,"name":"<main>","line":2,"column":1,"source":{"path":PATH}}]}"#,
+            r#""variables" ok {"variables":[{"name":"_","value":"3","type":"int","variablesReference":0}]}"#,
+            // the program's input is left for the program
+            r#""evaluate" failed "runtime error at 1:1: type error: cannot convert nil to int""#,
+            r#""evaluate" ok {"result":"1024","variablesReference":0}"#,
+            r#""output" {"category":"stdout","output":"1024\n"}"#,
+            r#""next" ok"#,
+            r#""output" {"category":"stdout","output":"42\n"}"#,
+            r#""stopped" {"reason":"step","threadId":1,"allThreadsStopped":true}"#,
+            r#""variables" ok {"variables":[{"name":"_","value":"42","type":"int","variablesReference":0}]}"#,
+            r#""setVariable" ok {"value":"40","type":"int","variablesReference":0}"#,
+            r#""continue" ok {"allThreadsContinued":true}"#,
+            r#""output" {"category":"stdout","output":"5\n"}"#,
+            r#""exited" {"exitCode":0}"#,
+            r#""terminated""#,
+            r#""disconnect" ok"#,
+        ];
+        let path = Json::from(path).to_string();
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|line| line.replace("PATH", &path))
+            .collect();
+        assert_eq!(transcript(&output), expected);
+    }
+
+    #[test]
+    fn setting_a_variable_sets_it_in_the_interpreter() {
+        let dir = std::env::temp_dir().join(format!("rickroust-dap-set-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("program.rr");
+        std::fs::write(&program, "print 1\nprint 2").unwrap();
+        let mut server = Server {
+            out: Sender {
+                writer: Vec::new(),
+                seq: 0,
+            },
+            session: None,
+        };
+        for request in [
+            request(
+                1,
+                "launch",
+                json_object! { "program" => program.to_str().unwrap(), "stopOnEntry" => true },
+            ),
+            request(2, "configurationDone", Json::Null),
+            request(3, "next", Json::Null),
+            request(
+                4,
+                "setVariable",
+                json_object! { "name" => "_", "value" => "3 * 7" },
+            ),
+        ] {
+            assert!(server.handle(&request).unwrap());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        let session = server.session.as_ref().unwrap();
+        assert_eq!(
+            session.interpreter.last_value(),
+            Some(&Value::Int(21.into()))
+        );
+        assert!(transcript(&server.out.writer)
+            .iter()
+            .all(|line| !line.contains("failed")));
+    }
+
+    #[test]
+    fn messages_are_limited() {
+        let header = format!("Content-Length: {}\r\n\r\n", MAX_MESSAGE + 1);
+        let err = read_message(&mut header.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            format!("message of {} bytes is too long", MAX_MESSAGE + 1)
+        );
+        assert!(read_message(&mut &b"Content-Length: 2\r\n\r\n{}"[..])
+            .unwrap()
+            .is_some());
+    }
+}
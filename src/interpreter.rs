@@ -1,32 +1,763 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, Instant};
+
+use crate::arith::Overflow;
+use crate::budget::{Budget, Stats};
+use crate::convert::IntoNative;
+use crate::lexer::Span;
+use crate::natives::Natives;
 use crate::parser::AST;
-use crate::lexer::Token;
+use crate::profiler::Profiler;
+use crate::replay::Recording;
+use crate::snapshot;
+use crate::value::Value;
+use crate::Program;
+
+#[derive(Debug, Clone)]
+pub enum ErrorKind {
+    DivisionByZero,
+    Overflow,
+    NegativeExponent,
+    InvalidOperator(String),
+    /// Operands of the wrong type
+    Type(String),
+    /// The output sink or input source failed
+    Io(String),
+    UnknownFunction(String),
+    Arity {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// A value that can't be converted, like `int("rick")`
+    Conversion(String),
+    /// A host function failed
+    Native(String),
+    /// An error from a recording or a snapshot, or a replay that went off its recording
+    Replayed(String),
+    OutOfFuel {
+        used: u64,
+    },
+    Timeout {
+        elapsed: Duration,
+    },
+    OutOfMemory {
+        requested: usize,
+        allocated: usize,
+        limit: usize,
+    },
+}
 
-pub struct Interpreter;
+/// A language-level error, attributed to the node that raised it.
+/// Made with `RuntimeError::new`, as it may gain fields.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub span: Span,
+    /// The calls it happened in, innermost first, not counting `<main>`
+    pub frames: Vec<Frame>,
+}
+
+/// A call to a function, on the stack when an error happened.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Frame {
+    pub name: String,
+    pub call: Span,
+}
+
+impl ErrorKind {
+    /// Whether a budget ran out, rather than the program going wrong.
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::OutOfFuel { .. } | ErrorKind::Timeout { .. } | ErrorKind::OutOfMemory { .. }
+        )
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::DivisionByZero => write!(f, "division by zero"),
+            ErrorKind::Overflow => write!(f, "integer overflow"),
+            ErrorKind::NegativeExponent => write!(f, "negative exponent"),
+            ErrorKind::InvalidOperator(op) => {
+                write!(f, "unexpected binary operator: `{}`", op)
+            }
+            ErrorKind::Type(message) => write!(f, "type error: {}", message),
+            ErrorKind::Io(message) => write!(f, "I/O error: {}", message),
+            ErrorKind::UnknownFunction(name) => write!(f, "unknown function `{}`", name),
+            ErrorKind::Arity {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` takes {} argument(s), but {} were given",
+                name, expected, found
+            ),
+            ErrorKind::Conversion(message) => write!(f, "conversion error: {}", message),
+            ErrorKind::Native(message) => write!(f, "native error: {}", message),
+            // recorded errors are shown as they were
+            ErrorKind::Replayed(message) => write!(f, "{}", message),
+            ErrorKind::OutOfFuel { used } => write!(f, "out of fuel after {} steps", used),
+            ErrorKind::Timeout { elapsed } => {
+                write!(f, "time limit exceeded after {:.3?}", elapsed)
+            }
+            ErrorKind::OutOfMemory {
+                requested,
+                allocated,
+                limit,
+            } => write!(
+                f,
+                "out of memory: {} more bytes would go over the limit of {} ({} in use)",
+                requested, limit, allocated
+            ),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "runtime error at {}: {}", self.span, self.kind)
+    }
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, span: Span) -> Self {
+        RuntimeError {
+            kind,
+            span,
+            frames: Vec::new(),
+        }
+    }
+
+    /// The error, as raised by a call to `name` at `call`. Budgets that ran out
+    /// aren't the function's doing, and are reported the same inside a call as outside.
+    pub(crate) fn in_call(mut self, name: &str, call: Span) -> Self {
+        if !self.kind.is_limit() {
+            self.frames.push(Frame {
+                name: name.to_string(),
+                call,
+            });
+        }
+        self
+    }
+
+    /// The error with its stack trace, and the line it's on if there's `source`.
+    pub fn report(&self, source: Option<&str>) -> String {
+        let mut report = self.to_string();
+        for frame in &self.frames {
+            report += &format!("\n  in `{}`, called at {}", frame.name, frame.call);
+        }
+        let span = self.frames.last().map_or(self.span, |frame| frame.call);
+        report += &format!("\n  in <main>, at {}", span);
+        if let Some(line) = source.and_then(|source| source.lines().nth(span.line.checked_sub(1)?))
+        {
+            let number = span.line.to_string();
+            report += &format!(
+                "\n    {} | {}\n    {} | {}^",
+                number,
+                line,
+                " ".repeat(number.len()),
+                " ".repeat(span.col.saturating_sub(1))
+            );
+        }
+        report
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Why `step` or `run_until` stopped before a statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseReason {
+    /// Just loaded, nothing has run yet
+    Entry,
+    /// One statement ran
+    Step,
+    /// The `run_until` predicate matched
+    Predicate,
+}
+
+/// Where a program run with `step` or `run_until` has got to.
+#[derive(Debug, Clone)]
+pub enum Status {
+    /// Stopped before the statement at `span`
+    Paused { span: Span, reason: PauseReason },
+    /// Every statement ran, this is the value of the last one
+    Finished(Value),
+    /// A statement failed, which ended the program
+    Failed(RuntimeError),
+}
+
+// a program run a statement at a time, see `Interpreter::load`
+struct Loaded {
+    statements: Vec<AST>,
+    pc: usize,
+    last: Option<Value>,
+    // how the statement at `pc` failed, if it did
+    failed: Option<RuntimeError>,
+    // where each statement started, while replaying, for `step_back`
+    history: Vec<Checkpoint>,
+}
+
+struct Checkpoint {
+    pc: usize,
+    last: Option<Value>,
+    steps: u64,
+    allocated: usize,
+    replayed: usize,
+}
+
+/// The statements of a program to `load`, one at a time.
+fn statements(program: &Program) -> Vec<AST> {
+    match program.ast() {
+        AST::Block(statements, _) => statements.clone(),
+        node => vec![node.clone()],
+    }
+}
+
+/// Standard input, locked for each read rather than for as long as it's held,
+/// so any number of interpreters (on any threads) can default to it.
+#[derive(Debug, Default)]
+pub struct Stdin {
+    // what `fill_buf` took from stdin's buffer and hasn't been consumed yet
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl io::Read for Stdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.buffer.len() {
+            let n = (&self.buffer[self.pos..]).read(buf)?;
+            self.consume(n);
+            return Ok(n);
+        }
+        io::stdin().read(buf)
+    }
+}
+
+impl BufRead for Stdin {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buffer.len() {
+            let mut stdin = io::stdin().lock();
+            let available = stdin.fill_buf()?;
+            self.buffer.clear();
+            self.buffer.extend_from_slice(available);
+            self.pos = 0;
+            let n = available.len();
+            stdin.consume(n);
+        }
+        Ok(&self.buffer[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.buffer.len());
+    }
+
+    fn read_line(&mut self, line: &mut String) -> io::Result<usize> {
+        if self.pos == self.buffer.len() {
+            return io::stdin().read_line(line);
+        }
+        // whatever an earlier `fill_buf` left over comes first
+        let buffered = &self.buffer[self.pos..];
+        let end = buffered.iter().position(|&b| b == b'\n');
+        let mut bytes = buffered[..end.map_or(buffered.len(), |i| i + 1)].to_vec();
+        self.consume(bytes.len());
+        if end.is_none() {
+            io::stdin().lock().read_until(b'\n', &mut bytes)?;
+        }
+        let text = String::from_utf8(bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        line.push_str(&text);
+        Ok(text.len())
+    }
+}
+
+/// Prints to `O` and reads from `I`, which default to stdout and [`Stdin`].
+/// Use e.g. a `Vec<u8>` and a `&[u8]` to capture a program's output
+/// and script its input.
+pub struct Interpreter<O: Write = io::Stdout, I: BufRead = Stdin> {
+    overflow: Overflow,
+    // set by `with_overflow`, which beats the program's pragma in `run`
+    overflow_chosen: bool,
+    budget: Budget,
+    output: O,
+    input: I,
+    natives: Natives,
+    trace: Option<Box<dyn Write>>,
+    profiler: Option<Profiler>,
+    // whether the profiler is in `<main>` for a loaded program, see `track_main`
+    in_main: bool,
+    // nesting of the node being evaluated, for the trace
+    depth: usize,
+    loaded: Option<Loaded>,
+}
 
 impl Interpreter {
     pub fn new() -> Self {
-        Interpreter
-    }
-
-    pub fn interpret(&mut self, node: &AST) -> i32 {
-        match node {
-            AST::BinOp(left, op, right) => {
-                let lhs = self.interpret(left);
-                let rhs = self.interpret(right);
-                match op {
-                    Token::Plus => lhs + rhs,
-                    Token::Minus => lhs - rhs,
-                    Token::Star => lhs * rhs,
-                    Token::Slash => lhs / rhs,
-                    _ => panic!("Unexpected binary operator"),
+        Interpreter::with_io(io::stdout(), Stdin::default())
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl<O: Write, I: BufRead> Interpreter<O, I> {
+    pub fn with_io(output: O, input: I) -> Self {
+        Interpreter {
+            overflow: Overflow::default(),
+            overflow_chosen: false,
+            budget: Budget::default(),
+            output,
+            input,
+            natives: Natives::default(),
+            trace: None,
+            profiler: None,
+            in_main: false,
+            depth: 0,
+            loaded: None,
+        }
+    }
+
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut O {
+        &mut self.output
+    }
+
+    pub fn into_output(self) -> O {
+        self.output
+    }
+
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self.overflow_chosen = true;
+        self
+    }
+
+    /// Stops with `ErrorKind::OutOfFuel` after evaluating `fuel` AST nodes.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.budget.fuel = Some(fuel);
+        self
+    }
+
+    /// Stops with `ErrorKind::Timeout` once `deadline` has passed.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.budget.deadline = Some(deadline);
+        self
+    }
+
+    /// Stops with `ErrorKind::Timeout` once the run has taken `timeout`,
+    /// counting from its first step like `stats` does.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.budget.timeout = Some(timeout);
+        self
+    }
+
+    /// Stops with `ErrorKind::OutOfMemory` instead of allocating more than `bytes`
+    /// for runtime values.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.budget.memory_limit = Some(bytes);
+        self
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.budget.stats()
+    }
+
+    /// Makes `function` callable from Rickroll as `name`, replacing any builtin of that name.
+    /// Its errors are reported at the call. Typed closures like `|a: i64, b: i64| a + b`
+    /// get their arguments counted and converted, raw `Fn(&[Value]) -> Result<Value, ErrorKind>`
+    /// ones check their own.
+    pub fn register_fn<Args>(&mut self, name: &str, function: impl IntoNative<Args>) {
+        self.natives.register(name, function);
+    }
+
+    /// Records what `input()` and host functions return, to replay the run later.
+    pub fn with_recording(mut self) -> Self {
+        self.natives.record();
+        self
+    }
+
+    /// Replays `recording` instead of reading input and calling host functions,
+    /// so the run goes exactly as the recorded one did. Enables `step_back`.
+    pub fn with_replay(mut self, recording: Recording) -> Self {
+        self.natives.replay(recording);
+        self
+    }
+
+    /// What's been recorded so far, with `with_recording`.
+    pub fn recording(&self) -> Option<&Recording> {
+        self.natives.recording()
+    }
+
+    /// Logs every evaluated node to `trace`, with its operands and result.
+    pub fn with_trace(mut self, trace: impl Write + 'static) -> Self {
+        self.trace = Some(Box::new(trace));
+        self
+    }
+
+    /// Records how often each line and function runs, and for how long.
+    pub fn with_profiler(mut self) -> Self {
+        self.profiler = Some(Profiler::default());
+        self
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Loads `program` to be run a statement at a time with `step` and `run_until`,
+    /// so a host can interleave it with its own work without threads.
+    /// Budgets, tracing and profiling apply, and the overflow mode is chosen, as with `run`.
+    pub fn load(&mut self, program: &Program) -> Status {
+        self.budget.restart();
+        if !self.overflow_chosen {
+            self.overflow = program.overflow().unwrap_or_default();
+        }
+        let statements = statements(program);
+        self.loaded = Some(Loaded {
+            statements,
+            pc: 0,
+            last: None,
+            failed: None,
+            history: Vec::new(),
+        });
+        self.track_main();
+        self.status(PauseReason::Entry)
+    }
+
+    /// Runs the next statement of the loaded program.
+    /// An error ends the program, later steps return it again.
+    pub fn step(&mut self) -> Result<Status, RuntimeError> {
+        if let Some(mut loaded) = self.loaded.take() {
+            if let Some(err) = &loaded.failed {
+                let err = err.clone();
+                self.loaded = Some(loaded);
+                return Err(err);
+            }
+            if let Some(replayed) = self.natives.replay_position() {
+                if loaded.pc < loaded.statements.len() {
+                    let stats = self.budget.stats();
+                    loaded.history.push(Checkpoint {
+                        pc: loaded.pc,
+                        last: loaded.last.clone(),
+                        steps: stats.steps,
+                        allocated: stats.allocated,
+                        replayed,
+                    });
                 }
             }
-            AST::Num(value) => *value,
-            AST::Print(expr) => {
-                let value = self.interpret(expr);
-                println!("{}", value);
-                value
+            let result = match loaded.statements.get(loaded.pc) {
+                Some(statement) => self.run_statement(statement).map(Some),
+                None => Ok(None),
+            };
+            match &result {
+                Ok(Some(value)) => {
+                    loaded.pc += 1;
+                    loaded.last = Some(value.clone());
+                }
+                Ok(None) => {}
+                Err(err) => loaded.failed = Some(err.clone()),
+            }
+            self.loaded = Some(loaded);
+            self.track_main();
+            result?;
+        }
+        Ok(self.status(PauseReason::Step))
+    }
+
+    /// Goes back to before the last statement `step` ran, with the values and usage
+    /// it had then. Only while replaying, as replaying again gives the same results.
+    /// Output that's been written stays written.
+    pub fn step_back(&mut self) -> Status {
+        if let Some(loaded) = &mut self.loaded {
+            if let Some(checkpoint) = loaded.history.pop() {
+                loaded.pc = checkpoint.pc;
+                loaded.last = checkpoint.last;
+                loaded.failed = None;
+                self.budget.resume(checkpoint.steps, checkpoint.allocated);
+                self.natives.rewind(checkpoint.replayed);
+            }
+        }
+        self.track_main();
+        self.status(PauseReason::Step)
+    }
+
+    /// Whether `step_back` can go back any further.
+    pub fn can_step_back(&self) -> bool {
+        self.loaded
+            .as_ref()
+            .is_some_and(|loaded| !loaded.history.is_empty())
+    }
+
+    /// Steps until `predicate` holds for the span of the next statement,
+    /// or the program finishes. At least one statement runs, so calling it
+    /// again after it paused moves on.
+    pub fn run_until(
+        &mut self,
+        mut predicate: impl FnMut(Span) -> bool,
+    ) -> Result<Status, RuntimeError> {
+        loop {
+            match self.step()? {
+                Status::Paused { span, .. } if predicate(span) => {
+                    return Ok(Status::Paused {
+                        span,
+                        reason: PauseReason::Predicate,
+                    })
+                }
+                Status::Paused { .. } => {}
+                finished => return Ok(finished),
             }
         }
     }
+
+    /// Saves the loaded program's progress between statements, to `restore` later,
+    /// possibly in another process. `None` if no program is loaded.
+    pub fn snapshot(&self) -> Option<Vec<u8>> {
+        let loaded = self.loaded.as_ref()?;
+        let stats = self.budget.stats();
+        Some(snapshot::encode(&snapshot::State {
+            program: snapshot::fingerprint(&loaded.statements),
+            pc: loaded.pc,
+            last: loaded.last.clone(),
+            failed: loaded.failed.clone(),
+            overflow: self.overflow,
+            steps: stats.steps,
+            allocated: stats.allocated,
+        }))
+    }
+
+    /// Loads `program` and carries on from a `snapshot` of it, with the overflow mode
+    /// and budget usage it had. Fails if the snapshot is corrupt or of another program.
+    pub fn restore(&mut self, program: &Program, snapshot: &[u8]) -> Result<Status, String> {
+        let state = snapshot::decode(snapshot)?;
+        let statements = statements(program);
+        if snapshot::fingerprint(&statements) != state.program || state.pc > statements.len() {
+            return Err("The snapshot is of a different program".to_string());
+        }
+        self.loaded = Some(Loaded {
+            statements,
+            pc: state.pc,
+            last: state.last,
+            failed: state.failed,
+            history: Vec::new(),
+        });
+        self.overflow = state.overflow;
+        self.budget.resume(state.steps, state.allocated);
+        self.track_main();
+        Ok(self.status(PauseReason::Entry))
+    }
+
+    /// The value of the last statement `step` ran, if any.
+    pub fn last_value(&self) -> Option<&Value> {
+        self.loaded.as_ref()?.last.as_ref()
+    }
+
+    /// Replaces the value of the last statement, which the program finishes with
+    /// if no other statement runs. For debuggers editing `_`.
+    pub(crate) fn set_last_value(&mut self, value: Value) {
+        if let Some(loaded) = &mut self.loaded {
+            loaded.last = Some(value);
+        }
+    }
+
+    fn status(&self, reason: PauseReason) -> Status {
+        let Some(loaded) = &self.loaded else {
+            return Status::Finished(Value::Nil);
+        };
+        if let Some(err) = &loaded.failed {
+            return Status::Failed(err.clone());
+        }
+        match loaded.statements.get(loaded.pc) {
+            Some(statement) => Status::Paused {
+                span: statement.span(),
+                reason,
+            },
+            None => Status::Finished(loaded.last.clone().unwrap_or(Value::Nil)),
+        }
+    }
+
+    /// Runs a whole `program`, returning the value of its last statement.
+    /// Its `#pragma overflow` applies unless `with_overflow` chose a mode.
+    /// Every run gets the whole budget, and `stats` only counts the latest.
+    pub fn run(&mut self, program: &Program) -> Result<Value, RuntimeError> {
+        self.budget.restart();
+        if !self.overflow_chosen {
+            self.overflow = program.overflow().unwrap_or_default();
+        }
+        let result = self.interpret(program.ast());
+        self.budget.finish();
+        result
+    }
+
+    pub(crate) fn interpret(&mut self, node: &AST) -> Result<Value, RuntimeError> {
+        self.budget
+            .tick()
+            .map_err(|kind| RuntimeError::new(kind, node.span()))?;
+        self.depth += 1;
+        let result = self.eval(node);
+        self.depth -= 1;
+        result
+    }
+
+    fn eval(&mut self, node: &AST) -> Result<Value, RuntimeError> {
+        let tracing = self.trace.is_some();
+        // the function called, for the stack trace if it fails
+        let mut call = None;
+        // operands are only rendered for the trace
+        let (operands, result) = match node {
+            AST::BinOp(left, op, right, _) => {
+                let lhs = self.interpret(left)?;
+                let rhs = self.interpret(right)?;
+                let budget = &mut self.budget;
+                let result = budget
+                    .charge(lhs.binary_size_hint(op, &rhs, self.overflow))
+                    .and_then(|_| {
+                        lhs.binary(op, &rhs, self.overflow, &mut |units| budget.work(units))
+                    });
+                let operands = tracing.then(|| format!("{} {} {}", lhs.repr(), op, rhs.repr()));
+                (operands, result)
+            }
+            AST::Num(value, _) => {
+                let value = self.overflow.narrow(value).map(Value::Int);
+                (None, value.and_then(|value| self.charge(value)))
+            }
+            AST::Print(expr, _) => {
+                let value = self.interpret(expr)?;
+                let budget = &mut self.budget;
+                let result = value
+                    .to_string_metered(&mut |units| budget.work(units))
+                    .and_then(|text| {
+                        writeln!(self.output, "{}", text)
+                            .map_err(|err| ErrorKind::Io(err.to_string()))
+                    })
+                    .map(|_| value);
+                (None, result)
+            }
+            AST::Call(name, args, _) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.interpret(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.enter(name);
+                let result = self
+                    .natives
+                    .call(name, &args, &mut self.input, self.overflow);
+                self.exit();
+                call = Some(name);
+                let result = result.and_then(|value| self.charge(value));
+                let operands = tracing.then(|| {
+                    let args: Vec<_> = args.iter().map(Value::repr).collect();
+                    format!("{}({})", name, args.join(", "))
+                });
+                (operands, result)
+            }
+            AST::Block(statements, _) => {
+                self.enter("<main>");
+                let result = self.run_statements(statements);
+                self.exit();
+                (None, Ok(result?))
+            }
+        };
+        if let Some(trace) = &mut self.trace {
+            let label = match node {
+                AST::BinOp(_, op, ..) => format!("BinOp({})", op),
+                AST::Num(..) => "Num".to_string(),
+                AST::Print(..) => "Print".to_string(),
+                AST::Call(name, ..) => format!("Call({})", name),
+                AST::Block(..) => "Block".to_string(),
+            };
+            let result = match &result {
+                Ok(value) => value.repr(),
+                Err(kind) => format!("error: {}", kind),
+            };
+            let indent = "  ".repeat(self.depth - 1);
+            // a broken trace shouldn't stop the program
+            let _ = match operands {
+                Some(operands) => writeln!(
+                    trace,
+                    "{}{} at {} → {} = {}",
+                    indent,
+                    label,
+                    node.span(),
+                    operands,
+                    result
+                ),
+                None => writeln!(trace, "{}{} at {} → {}", indent, label, node.span(), result),
+            };
+        }
+        result.map_err(|kind| {
+            let err = RuntimeError::new(kind, node.span());
+            match call {
+                Some(name) => err.in_call(name, node.span()),
+                None => err,
+            }
+        })
+    }
+
+    /// Evaluates to the value of the last statement, or nil if there are none.
+    fn run_statements(&mut self, statements: &[AST]) -> Result<Value, RuntimeError> {
+        let mut value = Value::Nil;
+        for statement in statements {
+            value = self.run_statement(statement)?;
+        }
+        Ok(value)
+    }
+
+    fn run_statement(&mut self, statement: &AST) -> Result<Value, RuntimeError> {
+        let started = self.profiler.is_some().then(Instant::now);
+        let result = self.interpret(statement);
+        if let (Some(profiler), Some(started)) = (&mut self.profiler, started) {
+            profiler.record_line(statement.span().line, started.elapsed());
+        }
+        result
+    }
+
+    /// Keeps the profiler in `<main>` while a loaded program has statements left,
+    /// as it is for the whole of a `run`, and stops the clock once it has none.
+    fn track_main(&mut self) {
+        let running = self
+            .loaded
+            .as_ref()
+            .is_some_and(|loaded| loaded.failed.is_none() && loaded.pc < loaded.statements.len());
+        if running == self.in_main {
+            return;
+        }
+        if running {
+            self.enter("<main>");
+        } else {
+            self.exit();
+            self.budget.finish();
+        }
+        self.in_main = running;
+    }
+
+    fn enter(&mut self, name: &str) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(name);
+        }
+    }
+
+    fn exit(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit();
+        }
+    }
+
+    /// Charges a value for its heap after the fact, when its size couldn't be known up front.
+    fn charge(&mut self, value: Value) -> Result<Value, ErrorKind> {
+        self.budget.charge(value.heap_size())?;
+        Ok(value)
+    }
 }
@@ -0,0 +1,416 @@
+// Just enough JSON for the debug adapter protocol
+
+use std::fmt;
+
+/// How deep arrays and objects can nest, so a crafted message can't overflow the stack
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // keeps insertion order, objects are small
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(n) if n.fract() == 0.0 => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn parse(input: &str) -> Result<Json, String> {
+        let mut parser = JsonParser {
+            bytes: input.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(format!("Trailing characters at {}", parser.pos));
+        }
+        Ok(value)
+    }
+}
+
+/// Builds a `Json::Object` from `key => value` pairs
+macro_rules! json_object {
+    ($($key:expr => $value:expr),* $(,)?) => {
+        crate::json::Json::Object(vec![$(($key.to_string(), crate::json::Json::from($value))),*])
+    };
+}
+pub(crate) use json_object;
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Self {
+        Json::Number(n.into())
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Self {
+        Json::Array(items)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.bytes.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), String> {
+        if self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(format!("Expected `{}` at {}", literal, self.pos))
+        }
+    }
+
+    /// Reads a value nested `depth` deep in arrays and objects.
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        self.skip_whitespace();
+        let next = self.bytes.get(self.pos);
+        if matches!(next, Some(b'[' | b'{')) && depth >= MAX_DEPTH {
+            return Err(format!("Nested too deeply at {}", self.pos));
+        }
+        match next {
+            Some(b'n') => self.expect("null").map(|_| Json::Null),
+            Some(b't') => self.expect("true").map(|_| Json::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.bytes.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Json::Array(items));
+                        }
+                        _ => return Err(format!("Expected `,` or `]` at {}", self.pos)),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.bytes.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    fields.push((key, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Json::Object(fields));
+                        }
+                        _ => return Err(format!("Expected `,` or `}}` at {}", self.pos)),
+                    }
+                }
+            }
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.pos;
+                while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') =
+                    self.bytes.get(self.pos)
+                {
+                    self.pos += 1;
+                }
+                let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap();
+                text.parse()
+                    .map(Json::Number)
+                    .map_err(|_| format!("Invalid number `{}`", text))
+            }
+            _ => Err(format!("Unexpected character at {}", self.pos)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(&b) = self.bytes.get(self.pos) else {
+                return Err("Unterminated string".to_string());
+            };
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self.bytes.get(self.pos).ok_or("Unterminated string")?;
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // characters outside the BMP are a pair of UTF-16 surrogates
+                            if (0xD800..=0xDBFF).contains(&code)
+                                && self.bytes[self.pos..].starts_with(b"\\u")
+                            {
+                                let start = self.pos;
+                                self.pos += 2;
+                                match self.hex4()? {
+                                    low @ 0xDC00..=0xDFFF => {
+                                        code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)
+                                    }
+                                    // not a pair after all, that escape is read on its own
+                                    _ => self.pos = start,
+                                }
+                            }
+                            // lone surrogates become U+FFFD
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        _ => return Err(format!("Invalid escape at {}", self.pos)),
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                _ => bytes.push(b),
+            }
+        }
+        String::from_utf8(bytes).map_err(|err| err.to_string())
+    }
+
+    /// The four hex digits of a `\u` escape.
+    fn hex4(&mut self) -> Result<u32, String> {
+        let hex = self
+            .bytes
+            .get(self.pos..self.pos + 4)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .ok_or("Invalid `\\u` escape")?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(std::str::from_utf8(hex).unwrap(), 16).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(text: &str) {
+        let json = Json::parse(text).unwrap();
+        assert_eq!(json.to_string(), text);
+        assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(r#"{"seq":1,"type":"request","arguments":{"lines":[1,2,3],"ok":true}}"#);
+        round_trip(r#"[null,false,"",[],{},[[{}]]]"#);
+        round_trip(r#"{"a":{"b":{"c":[1,{"d":null}]}}}"#);
+        round_trip(r#""quote \" backslash \\ newline \n tab \t control \u0001""#);
+        round_trip(r#""ünïcödé, 🎵""#);
+    }
+
+    #[test]
+    fn whitespace_and_order() {
+        let json = Json::parse(" {\n \"b\" : 1 ,\t\"a\" : [ 2 , 3 ] }\r\n").unwrap();
+        assert_eq!(json.to_string(), r#"{"b":1,"a":[2,3]}"#);
+        assert_eq!(json.get("a").and_then(Json::as_array).unwrap().len(), 2);
+        assert_eq!(json.get("b").and_then(Json::as_i64), Some(1));
+        assert_eq!(json.get("c"), None);
+    }
+
+    #[test]
+    fn numbers() {
+        let number = |text| match Json::parse(text).unwrap() {
+            Json::Number(n) => n,
+            json => panic!("{:?}", json),
+        };
+        assert_eq!(number("0"), 0.0);
+        assert_eq!(number("-12"), -12.0);
+        assert_eq!(number("3.25"), 3.25);
+        assert_eq!(number("1e3"), 1000.0);
+        assert_eq!(number("-2.5E-1"), -0.25);
+        assert_eq!(Json::parse("2.5").unwrap().as_i64(), None);
+        assert_eq!(Json::Number(42.0).to_string(), "42");
+    }
+
+    #[test]
+    fn escapes() {
+        let string = |text| Json::parse(text).unwrap().as_str().unwrap().to_string();
+        assert_eq!(string(r#""\/\b\f\r""#), "/\u{8}\u{c}\r");
+        assert_eq!(string(r#""\u00e9\u4E2D""#), "é中");
+        // a surrogate pair is one character
+        assert_eq!(string(r#""\ud83c\udfb5""#), "🎵");
+        assert_eq!(string(r#""\uD83C\uDFB5!""#), "🎵!");
+        // lone surrogates aren't characters at all
+        assert_eq!(string(r#""\ud83c""#), "\u{fffd}");
+        assert_eq!(string(r#""\udfb5\ud83c""#), "\u{fffd}\u{fffd}");
+        assert_eq!(string(r#""\ud83cx""#), "\u{fffd}x");
+        assert_eq!(string(r#""\ud83c\u0041""#), "\u{fffd}A");
+    }
+
+    #[test]
+    fn errors() {
+        for text in [
+            "",
+            "nul",
+            "[1,]",
+            "[1 2]",
+            "{\"a\" 1}",
+            "{\"a\":1,}",
+            "{a:1}",
+            "\"unterminated",
+            "\"bad \\x escape\"",
+            "\"\\u12\"",
+            "\"\\u+123\"",
+            "1-",
+            "--1",
+            "[] []",
+            "@",
+        ] {
+            assert!(Json::parse(text).is_err(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth| format!("{}null{}", "[{\"a\":".repeat(depth), "}]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_DEPTH / 2)).is_ok());
+        let deepest = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(Json::parse(&deepest).is_ok());
+        for text in [
+            format!("{}{}", "[".repeat(MAX_DEPTH + 1), "]".repeat(MAX_DEPTH + 1)),
+            nested(1_000_000),
+            "[".repeat(1_000_000),
+        ] {
+            let err = Json::parse(&text).unwrap_err();
+            assert!(err.starts_with("Nested too deeply at "), "{}", err);
+        }
+    }
+}
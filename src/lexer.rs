@@ -1,19 +1,62 @@
+use crate::bigint::Int;
+
 #[derive(Debug, Clone)]
 pub enum Token {
-    Number(i32),
+    Number(Int),
     Plus,
     Minus,
     Star,
     Slash,
+    Percent,
+    Pow,
     Print,
+    Ident(String),
     LParen,
     RParen,
+    Comma,
     EOF,
 }
 
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Percent => write!(f, "%"),
+            Token::Pow => write!(f, "**"),
+            Token::Print => write!(f, "print"),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+            Token::EOF => write!(f, "end of input"),
+        }
+    }
+}
+
+/// 1-based source position of a token or node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
 pub struct Lexer<'a> {
     input: std::str::Chars<'a>,
     current_char: Option<char>,
+    line: usize,
+    col: usize,
+    // where the most recently returned token started
+    span: Span,
 }
 
 impl<'a> Lexer<'a> {
@@ -21,17 +64,34 @@ impl<'a> Lexer<'a> {
         let mut lexer = Lexer {
             input: input.chars(),
             current_char: None,
+            line: 1,
+            col: 0,
+            span: Span::default(),
         };
         lexer.advance();
         lexer
     }
 
     fn advance(&mut self) {
+        if self.current_char == Some('\n') {
+            self.line += 1;
+            self.col = 0;
+        }
         self.current_char = self.input.next();
+        self.col += 1;
+    }
+
+    /// Position of the token last returned by `get_next_token`.
+    pub fn span(&self) -> Span {
+        self.span
     }
 
     pub fn get_next_token(&mut self) -> Result<Token, String> {
         while let Some(c) = self.current_char {
+            self.span = Span {
+                line: self.line,
+                col: self.col,
+            };
             match c {
                 '0'..='9' => {
                     return match self.number() {
@@ -49,12 +109,20 @@ impl<'a> Lexer<'a> {
                 }
                 '*' => {
                     self.advance();
+                    if self.current_char == Some('*') {
+                        self.advance();
+                        return Ok(Token::Pow);
+                    }
                     return Ok(Token::Star);
                 }
                 '/' => {
                     self.advance();
                     return Ok(Token::Slash);
                 }
+                '%' => {
+                    self.advance();
+                    return Ok(Token::Percent);
+                }
                 '(' => {
                     self.advance();
                     return Ok(Token::LParen);
@@ -63,25 +131,17 @@ impl<'a> Lexer<'a> {
                     self.advance();
                     return Ok(Token::RParen);
                 }
-                'p' => {
+                ',' => {
                     self.advance();
-                    if self.current_char == Some('r') {
+                    return Ok(Token::Comma);
+                }
+                'a'..='z' | 'A'..='Z' | '_' => return Ok(self.word()),
+                // comments (and `#pragma`s, which are read before lexing)
+                '#' => {
+                    while !matches!(self.current_char, Some('\n') | None) {
                         self.advance();
-                        if self.current_char == Some('i') {
-                            self.advance();
-                            if self.current_char == Some('n') {
-                                self.advance();
-                                if self.current_char == Some('t') {
-                                    self.advance();
-                                    return Ok(Token::Print);
-                                }
-                            }
-                        }
                     }
-                    return Err(format!(
-                        "Expected \"print\", found: `{:?}`",
-                        self.current_char
-                    ));
+                    continue;
                 }
                 // whitespace
                 ' ' | '\t' | '\n' | '\r' => {
@@ -91,10 +151,32 @@ impl<'a> Lexer<'a> {
                 _ => return Err(format!("Unexpected character: {}", c)),
             }
         }
+        self.span = Span {
+            line: self.line,
+            col: self.col,
+        };
         Ok(Token::EOF)
     }
 
-    fn number(&mut self) -> Result<Token, std::num::ParseIntError> {
+    // keywords and names
+    fn word(&mut self) -> Token {
+        let mut word = String::new();
+        while let Some(c) = self.current_char {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            word.push(c);
+            self.advance();
+        }
+        match word.as_str() {
+            "print" => Token::Print,
+            _ => Token::Ident(word),
+        }
+    }
+
+    // Literals can be arbitrarily long,
+    // it's up to the overflow mode to decide whether they fit
+    fn number(&mut self) -> Result<Token, String> {
         let mut result = String::new();
         while let Some(c) = self.current_char {
             if !c.is_ascii_digit() {
@@ -109,7 +191,7 @@ impl<'a> Lexer<'a> {
             self.advance();
         }
         // https://doc.rust-lang.org/reference/types/function-item
-        result.parse::<i32>().map(Token::Number)
+        result.parse::<Int>().map(Token::Number)
         // "
         // I hate to say this, but
         // this black-magic reminds me of JS:
@@ -0,0 +1,212 @@
+//! Rickroll-Rust: the Rickroll programming language as a library.
+//!
+//! Parse once with [`parse`], then run the [`Program`] with an [`Interpreter`]
+//! (or compile it to a [`Chunk`] for the [`Vm`]):
+//!
+//! ```
+//! let program = rickroust::parse("print 2 ** 10").unwrap();
+//! let mut interpreter = rickroust::Interpreter::with_io(Vec::new(), &b""[..]);
+//! let value = interpreter.run(&program).unwrap();
+//! assert_eq!(interpreter.output(), b"1024\n");
+//! assert_eq!(value, rickroust::Value::Int(1024.into()));
+//! ```
+//!
+//! [`eval`] does both in one go, with stdout and stdin. Hosts extend the language
+//! with [`Interpreter::register_fn`], and convert between [`Value`]s and Rust types
+//! with [`FromValue`] and [`IntoValue`].
+
+use std::fmt;
+
+mod arith;
+mod bigint;
+mod budget;
+mod builtins;
+mod bytecode;
+#[cfg(feature = "capi")]
+mod capi;
+/// The LLVM JIT behind `rickroust --backend=llvm`.
+#[cfg(feature = "llvm")]
+pub mod compiler;
+mod convert;
+/// The Debug Adapter Protocol server behind `rickroust dap`.
+pub mod dap;
+mod interpreter;
+mod json;
+mod lexer;
+mod natives;
+mod optimizer;
+mod parser;
+mod profiler;
+mod replay;
+mod rrc;
+#[cfg(feature = "llvm")]
+mod runtime;
+mod snapshot;
+#[cfg(test)]
+mod tests;
+mod value;
+mod vm;
+
+pub use arith::Overflow;
+pub use bigint::{BigInt, Int};
+pub use budget::Stats;
+pub use bytecode::Chunk;
+pub use convert::{FromValue, IntoNative, IntoNativeResult, IntoValue, RawArgs};
+pub use interpreter::{ErrorKind, Frame, Interpreter, PauseReason, RuntimeError, Status, Stdin};
+pub use lexer::Span;
+pub use natives::{expect_args, type_error};
+pub use profiler::{Entry, Profiler};
+pub use replay::{Event, Recording};
+pub use value::Value;
+pub use vm::Vm;
+
+/// A parsed program, ready to run. It owns its data, so it outlives the source,
+/// and it's immutable and `Send + Sync`: one parse can feed interpreters on many
+/// threads, each with its own state.
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let program = Arc::new(rickroust::parse("2 ** 10").unwrap());
+/// let workers: Vec<_> = (0..4)
+///     .map(|_| {
+///         let program = Arc::clone(&program);
+///         thread::spawn(move || rickroust::Interpreter::new().run(&program).unwrap())
+///     })
+///     .collect();
+/// for worker in workers {
+///     assert_eq!(worker.join().unwrap().to_string(), "1024");
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Program {
+    ast: parser::AST,
+    overflow: Option<Overflow>,
+}
+
+impl Program {
+    pub(crate) fn ast(&self) -> &parser::AST {
+        &self.ast
+    }
+
+    /// The mode from the source's `#pragma overflow`, if it has one.
+    pub fn overflow(&self) -> Option<Overflow> {
+        self.overflow
+    }
+
+    /// Folds constants and simplifies arithmetic, like `rickroust -O`. `overflow` is the
+    /// mode it'll run in, as results are only the same in that mode.
+    /// Fails if it divides by a constant zero.
+    pub fn optimize(self, overflow: Overflow) -> Result<Program, Diagnostics> {
+        let ast = optimizer::optimize(self.ast, overflow)
+            .map_err(|diagnostic| Diagnostics(vec![diagnostic]))?;
+        Ok(Program { ast, ..self })
+    }
+}
+
+// what's shared between threads has to stay shareable
+const _: fn() = || {
+    fn shareable<T: Send + Sync>() {}
+    shareable::<Program>();
+    shareable::<bytecode::Chunk>();
+    shareable::<Value>();
+};
+
+/// One problem found in the source.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    /// `None` for problems that aren't at any one place
+    pub span: Option<Span>,
+}
+
+/// Everything wrong with a source that failed to parse.
+#[derive(Debug, Clone)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    fn single(message: String, span: Option<Span>) -> Self {
+        Diagnostics(vec![Diagnostic { message, span }])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "error at {}: {}", span, self.message),
+            None => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+/// Lexes and parses `source`, and reads its `#pragma overflow`.
+/// Fails on expressions nested more than 128 deep, as running them could overflow the stack.
+pub fn parse(source: &str) -> Result<Program, Diagnostics> {
+    let overflow =
+        Overflow::from_pragma(source).map_err(|message| Diagnostics::single(message, None))?;
+    let mut parser = parser::Parser::new(lexer::Lexer::new(source)).map_err(|message| {
+        // the first token didn't lex, find out where it is
+        let mut lexer = lexer::Lexer::new(source);
+        let _ = lexer.get_next_token();
+        Diagnostics::single(message, Some(lexer.span()))
+    })?;
+    let ast = parser
+        .parse()
+        .map_err(|message| Diagnostics::single(message, Some(parser.span())))?;
+    Ok(Program { ast, overflow })
+}
+
+/// Why [`eval`] failed.
+#[derive(Debug)]
+pub enum EvalError {
+    Parse(Diagnostics),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Parse(diagnostics) => write!(f, "{}", diagnostics),
+            EvalError::Runtime(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl From<Diagnostics> for EvalError {
+    fn from(diagnostics: Diagnostics) -> Self {
+        EvalError::Parse(diagnostics)
+    }
+}
+
+impl From<RuntimeError> for EvalError {
+    fn from(err: RuntimeError) -> Self {
+        EvalError::Runtime(err)
+    }
+}
+
+/// Parses and interprets `source` with stdout and stdin, returning the value of its last statement.
+pub fn eval(source: &str) -> Result<Value, EvalError> {
+    let program = parse(source)?;
+    Ok(Interpreter::new().run(&program)?)
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::rc::Rc;
+
+use crate::arith::Overflow;
+use crate::builtins;
+use crate::convert::IntoNative;
+use crate::interpreter::ErrorKind;
+use crate::replay::{Log, Recording};
+use crate::value::Value;
+
+/// A host function callable from Rickroll. Errors are attributed to the call's span.
+pub type NativeFn = Rc<dyn Fn(&[Value]) -> Result<Value, ErrorKind>>;
+
+/// Host functions registered with `register_fn`, shared by `Interpreter` and `Vm`.
+/// They're looked up before the builtins, so a host can replace e.g. `input`.
+/// They can also record what `input()` and natives return, or replay a recording.
+#[derive(Clone, Default)]
+pub struct Natives {
+    functions: HashMap<String, NativeFn>,
+    log: Option<Log>,
+}
+
+impl Natives {
+    pub fn register<Args>(&mut self, name: &str, function: impl IntoNative<Args>) {
+        self.functions
+            .insert(name.to_string(), function.into_native(name));
+    }
+
+    pub fn record(&mut self) {
+        self.log = Some(Log::Record(Recording::default()));
+    }
+
+    /// Takes calls' results from `recording` instead of making them.
+    pub fn replay(&mut self, recording: Recording) {
+        self.log = Some(Log::Replay { recording, next: 0 });
+    }
+
+    /// What's been recorded so far, if recording.
+    pub fn recording(&self) -> Option<&Recording> {
+        match &self.log {
+            Some(Log::Record(recording)) => Some(recording),
+            _ => None,
+        }
+    }
+
+    /// How far into its recording a replay is.
+    pub(crate) fn replay_position(&self) -> Option<usize> {
+        match &self.log {
+            Some(Log::Replay { next, .. }) => Some(*next),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn rewind(&mut self, position: usize) {
+        if let Some(Log::Replay { next, .. }) = &mut self.log {
+            *next = position;
+        }
+    }
+
+    /// Calls the native `name`, or the builtin if there's no such native.
+    /// Ints natives return are narrowed like literals.
+    pub fn call(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        input: &mut impl BufRead,
+        overflow: Overflow,
+    ) -> Result<Value, ErrorKind> {
+        // calls to unknown functions fail the same every time, so they aren't logged,
+        // but a replay can stand in for a host function that isn't registered
+        let logged = self.functions.contains_key(name) || builtins::is_impure(name);
+        match &mut self.log {
+            Some(Log::Replay { recording, next })
+                if logged
+                    || recording
+                        .events()
+                        .get(*next)
+                        .is_some_and(|event| event.name == name) =>
+            {
+                let result = recording.replay(*next, name);
+                *next += 1;
+                return result;
+            }
+            Some(Log::Record(_)) if logged => {
+                let result = self.call_live(name, args, input, overflow);
+                if let Some(Log::Record(recording)) = &mut self.log {
+                    recording.push(name, &result);
+                }
+                return result;
+            }
+            _ => {}
+        }
+        self.call_live(name, args, input, overflow)
+    }
+
+    fn call_live(
+        &self,
+        name: &str,
+        args: &[Value],
+        input: &mut impl BufRead,
+        overflow: Overflow,
+    ) -> Result<Value, ErrorKind> {
+        match self.functions.get(name) {
+            Some(function) => match function(args)? {
+                Value::Int(value) => overflow.narrow(&value).map(Value::Int),
+                value => Ok(value),
+            },
+            None => builtins::call(name, args, input, overflow),
+        }
+    }
+}
+
+/// Fails unless `name` got exactly `expected` arguments.
+pub fn expect_args(name: &str, args: &[Value], expected: usize) -> Result<(), ErrorKind> {
+    builtins::arity(name, args, expected)
+}
+
+/// The error for a native's argument `index` (from 0) that isn't of the `expected` type.
+pub fn type_error(name: &str, index: usize, expected: &str, found: &Value) -> ErrorKind {
+    ErrorKind::Type(format!(
+        "argument {} of `{}` must be {}, not {}",
+        index + 1,
+        name,
+        expected,
+        found.type_name()
+    ))
+}
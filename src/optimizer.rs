@@ -0,0 +1,140 @@
+// The `-O` pass: folds constant arithmetic and drops operations that can't
+// change an int, like `x * 1` and `x + 0`. Folding uses the overflow mode the
+// program will run in, so results and errors are the same as without it, only
+// fewer nodes are left to evaluate (and to spend fuel on). A division by a
+// constant zero is reported up front, whatever it divides; other errors, like
+// overflow, are left to the run, so they come after whatever the program printed
+// before them. Big results aren't folded either: working them out here would
+// take time and memory no budget is charged for.
+//
+// `x - x` isn't simplified: without variables, an `x` that isn't a constant
+// has a call in it, and both calls have to happen.
+
+use crate::arith::Overflow;
+use crate::bigint::{self, Int};
+use crate::lexer::{Span, Token};
+use crate::parser::AST;
+use crate::value::Value;
+use crate::Diagnostic;
+
+/// The most heap a folded constant may take, in bytes.
+const MAX_FOLDED_SIZE: usize = 128;
+
+/// Optimizes `node` for running with `overflow`.
+pub fn optimize(node: AST, overflow: Overflow) -> Result<AST, Diagnostic> {
+    Ok(match node {
+        AST::BinOp(left, op, right, span) => {
+            let left = optimize(*left, overflow)?;
+            let right = optimize(*right, overflow)?;
+            if matches!(op, Token::Slash | Token::Percent)
+                && matches!(&right, AST::Num(divisor, _) if divisor.is_zero())
+            {
+                return Err(Diagnostic {
+                    message: "Division by zero".to_string(),
+                    span: Some(span),
+                });
+            }
+            if let (AST::Num(a, _), AST::Num(b, _)) = (&left, &right) {
+                if let Some(value) = fold(&op, a, b, overflow) {
+                    return Ok(AST::Num(value, span));
+                }
+            }
+            simplify(left, op, right, span)
+        }
+        AST::Print(expr, span) => AST::Print(Box::new(optimize(*expr, overflow)?), span),
+        AST::Call(name, args, span) => {
+            let args = args
+                .into_iter()
+                .map(|arg| optimize(arg, overflow))
+                .collect::<Result<_, _>>()?;
+            AST::Call(name, args, span)
+        }
+        AST::Block(statements, span) => {
+            let statements = statements
+                .into_iter()
+                .map(|statement| optimize(statement, overflow))
+                .collect::<Result<_, _>>()?;
+            AST::Block(statements, span)
+        }
+        node @ AST::Num(..) => node,
+    })
+}
+
+/// `a op b`, if it's an int that's cheap to work out and keep.
+fn fold(op: &Token, a: &Int, b: &Int, overflow: Overflow) -> Option<Int> {
+    // literals are narrowed before they're used, as when running
+    let (a, b) = (overflow.narrow(a).ok()?, overflow.narrow(b).ok()?);
+    let size = Value::Int(a.clone()).binary_size_hint(op, &Value::Int(b.clone()), overflow);
+    if size > MAX_FOLDED_SIZE {
+        return None;
+    }
+    overflow.apply(op, &a, &b, &mut bigint::unmetered).ok()
+}
+
+/// `left op right` without the operation if it's an identity on ints.
+fn simplify(left: AST, op: Token, right: AST, span: Span) -> AST {
+    let is = |node: &AST, constant: i32| matches!(node, AST::Num(Int::Small(value), _) if *value == constant);
+    match op {
+        Token::Plus | Token::Minus if is(&right, 0) && is_int(&left) => left,
+        Token::Plus if is(&left, 0) && is_int(&right) => right,
+        Token::Star | Token::Slash | Token::Pow if is(&right, 1) && is_int(&left) => left,
+        Token::Star if is(&left, 1) && is_int(&right) => right,
+        _ => AST::BinOp(Box::new(left), op, Box::new(right), span),
+    }
+}
+
+/// Whether `node` is an int if it evaluates at all.
+/// Identities don't hold for everything, e.g. `-0.0 + 0` is `0.0`.
+fn is_int(node: &AST) -> bool {
+    match node {
+        AST::Num(..) => true,
+        AST::BinOp(left, _, right, _) => is_int(left) && is_int(right),
+        // even `int` could be a host function that returns anything
+        AST::Print(..) | AST::Call(..) | AST::Block(..) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn optimized(source: &str, overflow: Overflow) -> Result<AST, Diagnostic> {
+        let AST::Block(mut statements, _) = crate::parse(source).unwrap().ast().clone() else {
+            unreachable!()
+        };
+        let AST::Print(expr, _) = statements.remove(0) else {
+            unreachable!()
+        };
+        optimize(*expr, overflow)
+    }
+
+    #[test]
+    fn folds_small_constants() {
+        let folded = optimized("print 2 ** 10 * 3 + 1", Overflow::Checked).unwrap();
+        assert!(
+            matches!(folded, AST::Num(Int::Small(3073), _)),
+            "{:?}",
+            folded
+        );
+    }
+
+    #[test]
+    fn leaves_big_results_to_the_run() {
+        let folded = optimized("print 3 ** 3000000", Overflow::Arbitrary).unwrap();
+        assert!(matches!(folded, AST::BinOp(..)), "{:?}", folded);
+    }
+
+    #[test]
+    fn division_by_zero_whatever_is_divided() {
+        for source in ["print 1 / 0", "print input() % 0", "print int(2) / (1 - 1)"] {
+            let error = optimized(source, Overflow::Checked).unwrap_err();
+            assert_eq!(error.message, "Division by zero");
+        }
+    }
+
+    #[test]
+    fn calls_are_not_assumed_to_be_ints() {
+        let folded = optimized("print int(1) + 0", Overflow::Checked).unwrap();
+        assert!(matches!(folded, AST::BinOp(..)), "{:?}", folded);
+    }
+}
@@ -1,15 +1,60 @@
-use crate::lexer::{Lexer, Token};
+use crate::bigint::Int;
+use crate::lexer::{Lexer, Span, Token};
 
-#[derive(Debug)]
+/// How deep expressions can nest. Everything after parsing walks the tree
+/// recursively, so this keeps a program from overflowing the host's stack.
+pub const MAX_DEPTH: usize = 128;
+const TOO_DEEP: &str = "Expression nested too deeply";
+
+/// A parsed expression and how deep it nests.
+type Parsed = (AST, usize);
+
+/// `node` over children that nest `depth` deep, unless that's too deep.
+fn deeper(node: AST, depth: usize) -> Result<Parsed, String> {
+    if depth == MAX_DEPTH {
+        return Err(TOO_DEEP.to_string());
+    }
+    Ok((node, depth + 1))
+}
+
+fn bin_op(
+    (left, left_depth): Parsed,
+    op: Token,
+    (right, right_depth): Parsed,
+    span: Span,
+) -> Result<Parsed, String> {
+    let node = AST::BinOp(Box::new(left), op, Box::new(right), span);
+    deeper(node, left_depth.max(right_depth))
+}
+
+#[derive(Debug, Clone)]
 pub enum AST {
-    BinOp(Box<AST>, Token, Box<AST>),
-    Num(i32),
-    Print(Box<AST>),
+    BinOp(Box<AST>, Token, Box<AST>, Span),
+    Num(Int, Span),
+    Print(Box<AST>, Span),
+    Call(String, Vec<AST>, Span),
+    /// A whole program, one statement after the other
+    Block(Vec<AST>, Span),
+}
+
+impl AST {
+    pub fn span(&self) -> Span {
+        match self {
+            AST::BinOp(.., span)
+            | AST::Num(_, span)
+            | AST::Print(_, span)
+            | AST::Call(.., span)
+            | AST::Block(_, span) => *span,
+        }
+    }
 }
 
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     current_token: Token,
+    current_span: Span,
+    // parentheses, calls and `**`s the parser is inside of
+    nesting: usize,
 }
 
 impl<'a> Parser<'a> {
@@ -17,11 +62,19 @@ impl<'a> Parser<'a> {
         let mut parser = Parser {
             lexer,
             current_token: Token::EOF,
+            current_span: Span::default(),
+            nesting: 0,
         };
         parser.current_token = parser.lexer.get_next_token()?;
+        parser.current_span = parser.lexer.span();
         Ok(parser)
     }
 
+    /// Position of the current token, which is where a parse error was found.
+    pub fn span(&self) -> Span {
+        self.lexer.span()
+    }
+
     #[must_use = "Don't ignore err!"]
     fn eat(&mut self, token: Token) -> Result<(), String> {
         if std::mem::discriminant(&self.current_token) != std::mem::discriminant(&token) {
@@ -31,51 +84,110 @@ impl<'a> Parser<'a> {
             ));
         }
         self.current_token = self.lexer.get_next_token()?;
+        self.current_span = self.lexer.span();
         Ok(())
     }
 
-    fn factor(&mut self) -> Result<AST, String> {
-        match self.current_token {
+    /// Parses with `parse`, one level deeper, failing instead of running out of stack.
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Parsed, String>) -> Result<Parsed, String> {
+        if self.nesting == MAX_DEPTH {
+            return Err(TOO_DEEP.to_string());
+        }
+        self.nesting += 1;
+        let parsed = parse(self);
+        self.nesting -= 1;
+        parsed
+    }
+
+    fn factor(&mut self) -> Result<Parsed, String> {
+        match self.current_token.clone() {
             Token::Number(value) => {
-                self.eat(Token::Number(value))?;
-                Ok(AST::Num(value))
+                let span = self.current_span;
+                self.eat(Token::Number(value.clone()))?;
+                Ok((AST::Num(value, span), 1))
             }
             Token::LParen => {
                 self.eat(Token::LParen)?;
-                let node = self.expr()?;
+                let parsed = self.nested(Self::expr)?;
                 self.eat(Token::RParen)?;
-                Ok(node)
+                Ok(parsed)
+            }
+            Token::Ident(name) => {
+                let span = self.current_span;
+                self.eat(Token::Ident(name.clone()))?;
+                self.eat(Token::LParen)?;
+                let mut args = Vec::new();
+                let mut depth = 0;
+                if !matches!(self.current_token, Token::RParen) {
+                    loop {
+                        let (arg, arg_depth) = self.nested(Self::expr)?;
+                        args.push(arg);
+                        depth = depth.max(arg_depth);
+                        if !matches!(self.current_token, Token::Comma) {
+                            break;
+                        }
+                        self.eat(Token::Comma)?;
+                    }
+                }
+                self.eat(Token::RParen)?;
+                deeper(AST::Call(name, args, span), depth)
             }
             _ => Err(format!("Unexpected token: {:?}", self.current_token)),
         }
     }
 
-    fn term(&mut self) -> Result<AST, String> {
-        let mut node = self.factor()?;
-        while let Token::Plus | Token::Minus | Token::Star | Token::Slash = self.current_token {
+    // `**` binds tighter than anything else, and to the right: 2 ** 3 ** 2 = 2 ** 9
+    fn power(&mut self) -> Result<Parsed, String> {
+        let node = self.factor()?;
+        if let Token::Pow = self.current_token {
+            let span = self.current_span;
+            self.eat(Token::Pow)?;
+            let right = self.nested(Self::power)?;
+            return bin_op(node, Token::Pow, right, span);
+        }
+        Ok(node)
+    }
+
+    fn term(&mut self) -> Result<Parsed, String> {
+        let mut node = self.power()?;
+        while let Token::Plus | Token::Minus | Token::Star | Token::Slash | Token::Percent =
+            self.current_token
+        {
             let token = self.current_token.clone();
+            let span = self.current_span;
             self.eat(token.clone())?;
-            node = AST::BinOp(Box::new(node), token, Box::new(self.factor()?));
+            node = bin_op(node, token, self.power()?, span)?;
         }
         Ok(node)
     }
 
-    fn expr(&mut self) -> Result<AST, String> {
+    fn expr(&mut self) -> Result<Parsed, String> {
         let mut node = self.term()?;
         while let Token::Plus | Token::Minus = self.current_token {
             let token = self.current_token.clone();
+            let span = self.current_span;
             self.eat(token.clone())?;
-            node = AST::BinOp(Box::new(node), token, Box::new(self.term()?));
+            node = bin_op(node, token, self.term()?, span)?;
         }
         Ok(node)
     }
 
-    pub fn parse(&mut self) -> Result<AST, String> {
+    fn statement(&mut self) -> Result<AST, String> {
         if let Token::Print = self.current_token {
+            let span = self.current_span;
             self.eat(Token::Print)?;
-            Ok(AST::Print(Box::new(self.expr()?)))
+            Ok(AST::Print(Box::new(self.expr()?.0), span))
         } else {
-            self.expr()
+            Ok(self.expr()?.0)
+        }
+    }
+
+    pub fn parse(&mut self) -> Result<AST, String> {
+        let span = self.current_span;
+        let mut statements = Vec::new();
+        while !matches!(self.current_token, Token::EOF) {
+            statements.push(self.statement()?);
         }
+        Ok(AST::Block(statements, span))
     }
 }
@@ -0,0 +1,159 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// How often something ran, and for how long in total.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Entry {
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl Entry {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+struct Frame {
+    name: String,
+    started: Instant,
+    // time spent in calls made from this frame
+    children: Duration,
+}
+
+/// Time spent per source line and per function: `<main>`, the builtins and the
+/// host's functions. Rickroll has no functions of its own (yet), so there are no
+/// verses to profile. Line and function times include everything they called.
+#[derive(Default)]
+pub struct Profiler {
+    lines: BTreeMap<usize, Entry>,
+    functions: BTreeMap<String, Entry>,
+    // self time per call stack, as in `<main>;int;input`
+    stacks: HashMap<String, Duration>,
+    frames: Vec<Frame>,
+}
+
+impl Profiler {
+    pub fn record_line(&mut self, line: usize, time: Duration) {
+        self.lines.entry(line).or_default().add(time);
+    }
+
+    pub fn enter(&mut self, name: &str) {
+        self.frames.push(Frame {
+            name: name.to_string(),
+            started: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    pub fn exit(&mut self) {
+        let stack = self
+            .frames
+            .iter()
+            .map(|frame| frame.name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let frame = self.frames.pop().expect("`exit` without `enter`");
+        let time = frame.started.elapsed();
+        *self.stacks.entry(stack).or_default() += time.saturating_sub(frame.children);
+        self.functions.entry(frame.name).or_default().add(time);
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += time;
+        }
+    }
+
+    pub fn lines(&self) -> &BTreeMap<usize, Entry> {
+        &self.lines
+    }
+
+    pub fn functions(&self) -> &BTreeMap<String, Entry> {
+        &self.functions
+    }
+
+    /// Prints per-line and per-function tables, slowest first.
+    pub fn report(&self, w: &mut impl Write) -> io::Result<()> {
+        let total = self
+            .functions
+            .get("<main>")
+            .map_or(Duration::ZERO, |main| main.time);
+        let percent = |time: Duration| {
+            if total.is_zero() {
+                0.0
+            } else {
+                100.0 * time.as_secs_f64() / total.as_secs_f64()
+            }
+        };
+
+        let mut lines: Vec<_> = self.lines.iter().collect();
+        lines.sort_by_key(|(_, entry)| Reverse(entry.time));
+        writeln!(w, "{:>8} {:>10} {:>12} {:>7}", "line", "count", "time", "%")?;
+        for (line, entry) in lines {
+            writeln!(
+                w,
+                "{:>8} {:>10} {:>12} {:>6.1}%",
+                line,
+                entry.count,
+                format!("{:.3?}", entry.time),
+                percent(entry.time)
+            )?;
+        }
+
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by_key(|(_, entry)| Reverse(entry.time));
+        writeln!(w)?;
+        writeln!(
+            w,
+            "{:>8} {:>10} {:>12} {:>7}",
+            "function", "calls", "time", "%"
+        )?;
+        for (name, entry) in functions {
+            writeln!(
+                w,
+                "{:>8} {:>10} {:>12} {:>6.1}%",
+                name,
+                entry.count,
+                format!("{:.3?}", entry.time),
+                percent(entry.time)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes self times in microseconds as folded stacks,
+    /// the input format of `flamegraph.pl` and `inferno-flamegraph`.
+    pub fn write_folded(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        for (stack, time) in stacks {
+            writeln!(w, "{} {}", stack, time.as_micros())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::{Interpreter, Status};
+
+    #[test]
+    fn stepped_runs_are_in_main() {
+        let program = crate::parse("print int(1)\nprint twice(2)").unwrap();
+        let mut interpreter = Interpreter::with_io(Vec::new(), &b""[..]).with_profiler();
+        interpreter.register_fn("twice", |x: i64| x * 2);
+        interpreter.load(&program);
+        while let Status::Paused { .. } = interpreter.step().unwrap() {}
+
+        let profiler = interpreter.profiler().unwrap();
+        assert_eq!(profiler.functions()["<main>"].count, 1);
+        assert_eq!(profiler.functions()["int"].count, 1);
+        assert_eq!(profiler.functions()["twice"].count, 1);
+        assert_eq!(profiler.lines().len(), 2);
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        assert!(folded.contains("<main>;twice "), "{}", folded);
+    }
+}
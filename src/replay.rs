@@ -0,0 +1,188 @@
+// Recordings of what a run got from outside, for replaying it exactly.
+//
+// `input()` and host functions are the only nondeterminism there is: the other
+// builtins are pure and there's no clock or randomness. A recording is what each
+// of those calls returned, in order, and a replay returns the same without
+// reading input or calling the host.
+//
+//   magic      b"RRT\0"
+//   version    u16, little endian
+//   checksum   u32, little endian, CRC-32 of everything after it
+//   events     count, then per call the function's name and a tag:
+//              0 and the value it returned, or 1 and its error message
+//
+// Numbers, text and values are written as in snapshots.
+
+use crate::interpreter::ErrorKind;
+use crate::rrc::{self, Reader};
+use crate::snapshot;
+use crate::value::Value;
+
+pub const MAGIC: &[u8; 4] = b"RRT\0";
+pub const VERSION: u16 = 1;
+const HEADER_LEN: usize = 10;
+
+/// One call to `input()` or a host function, and what it returned.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub name: String,
+    /// Errors are kept as they were displayed.
+    pub result: Result<Value, String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    events: Vec<Event>,
+}
+
+/// What `Natives` does with nondeterministic calls.
+#[derive(Clone)]
+pub(crate) enum Log {
+    Record(Recording),
+    Replay { recording: Recording, next: usize },
+}
+
+impl Recording {
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub(crate) fn push(&mut self, name: &str, result: &Result<Value, ErrorKind>) {
+        self.events.push(Event {
+            name: name.to_string(),
+            result: result.clone().map_err(|kind| kind.to_string()),
+        });
+    }
+
+    /// What the call at `next` returned, if it was to `name`.
+    pub(crate) fn replay(&self, next: usize, name: &str) -> Result<Value, ErrorKind> {
+        match self.events.get(next) {
+            Some(event) if event.name == name => event.result.clone().map_err(ErrorKind::Replayed),
+            Some(event) => Err(ErrorKind::Replayed(format!(
+                "replay diverged: called `{}`, but the recording has `{}` here",
+                name, event.name
+            ))),
+            None => Err(ErrorKind::Replayed(format!(
+                "replay diverged: called `{}` after the end of the recording",
+                name
+            ))),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        rrc::write_count(&mut body, self.events.len());
+        for event in &self.events {
+            rrc::write_text(&mut body, &event.name);
+            match &event.result {
+                Ok(value) => {
+                    body.push(0);
+                    snapshot::write_value(&mut body, value);
+                }
+                Err(message) => {
+                    body.push(1);
+                    rrc::write_text(&mut body, message);
+                }
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&rrc::crc32(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) {
+            return Err("Not a recording".to_string());
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(format!(
+                "Recording version {}, but this is version {}",
+                version, VERSION
+            ));
+        }
+        let checksum = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+        let body = &bytes[HEADER_LEN..];
+        if rrc::crc32(body) != checksum {
+            return Err("Recording checksum mismatch, the file is corrupt".to_string());
+        }
+
+        let mut reader = Reader::new(body);
+        let mut events = Vec::new();
+        for _ in 0..reader.count()? {
+            let name = reader.text()?.to_string();
+            let result = match reader.byte()? {
+                0 => Ok(snapshot::read_value(&mut reader, 0)?),
+                1 => Err(reader.text()?.to_string()),
+                tag => return Err(format!("Invalid event tag {} in recording", tag)),
+            };
+            events.push(Event { name, result });
+        }
+        if !reader.is_empty() {
+            return Err("Trailing bytes after recording".to_string());
+        }
+        Ok(Recording { events })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    const SOURCE: &str = "print int(input()) * 2\nprint twice(5)\nprint input()\nprint nope()";
+
+    fn recorded() -> (Vec<u8>, String, Recording) {
+        let program = crate::parse(SOURCE).unwrap();
+        let mut interpreter = Interpreter::with_io(Vec::new(), &b"21\n"[..]).with_recording();
+        interpreter.register_fn("twice", |x: i64| x * 2);
+        let err = interpreter.run(&program).unwrap_err();
+        let recording = interpreter.recording().unwrap().clone();
+        (interpreter.into_output(), err.kind.to_string(), recording)
+    }
+
+    #[test]
+    fn replays_the_run() {
+        let (output, err, recording) = recorded();
+        assert_eq!(output, b"42\n10\nnil\n");
+        // `nope` never got to a host function, so it isn't logged
+        let names: Vec<_> = recording
+            .events()
+            .iter()
+            .map(|event| &event.name[..])
+            .collect();
+        assert_eq!(names, ["input", "twice", "input"]);
+
+        let recording = Recording::decode(&recording.encode()).unwrap();
+        // no input and no host function, it all comes from the recording
+        let program = crate::parse(SOURCE).unwrap();
+        let mut replay = Interpreter::with_io(Vec::new(), &b""[..]).with_replay(recording);
+        let replayed = replay.run(&program).unwrap_err();
+        assert_eq!(replay.output(), &output);
+        assert_eq!(replayed.kind.to_string(), err);
+    }
+
+    #[test]
+    fn rejects_corruption() {
+        let bytes = recorded().2.encode();
+        for len in 0..bytes.len() {
+            assert!(Recording::decode(&bytes[..len]).is_err(), "cut at {}", len);
+        }
+        for i in 0..bytes.len() {
+            for bit in 0..8 {
+                let mut corrupt = bytes.clone();
+                corrupt[i] ^= 1 << bit;
+                assert!(
+                    Recording::decode(&corrupt).is_err(),
+                    "byte {} bit {}",
+                    i,
+                    bit
+                );
+            }
+        }
+    }
+}
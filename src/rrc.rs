@@ -0,0 +1,367 @@
+// The `.rrc` format for compiled programs, which the VM runs without the source.
+//
+//   magic      b"RRC\0"
+//   version    u16, little endian
+//   checksum   u32, little endian, CRC-32 of everything after it
+//   overflow   u8, 0 if the source has no `#pragma overflow`, else 1 + the mode
+//   constants  count, then per constant a tag byte and its value:
+//              0 small int (zigzag), 1 big int (decimal text), 2 string (UTF-8 text)
+//   code       count, then per instruction an opcode byte and its operands
+//   spans      per instruction its line, column and ticks
+//
+// Counts and other numbers are unsigned LEB128, text is a length and the bytes.
+
+use crate::arith::Overflow;
+use crate::bigint::Int;
+use crate::bytecode::{Chunk, Op};
+use crate::lexer::Span;
+use crate::value::Value;
+
+pub const MAGIC: &[u8; 4] = b"RRC\0";
+pub const VERSION: u16 = 2;
+const HEADER_LEN: usize = 10;
+
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn encode(chunk: &Chunk, overflow: Option<Overflow>) -> Vec<u8> {
+    let mut body = vec![match overflow {
+        Some(mode) => 1 + mode.index(),
+        None => 0,
+    }];
+    write_count(&mut body, chunk.constants.len());
+    for constant in &chunk.constants {
+        match constant {
+            &Value::Int(Int::Small(value)) => {
+                body.push(0);
+                // zigzag, so small negative numbers stay short
+                write_varint(&mut body, ((value << 1) ^ (value >> 31)) as u32 as u64);
+            }
+            Value::Int(value) => {
+                body.push(1);
+                write_text(&mut body, &value.to_string());
+            }
+            Value::Str(text) => {
+                body.push(2);
+                write_text(&mut body, text);
+            }
+            _ => unreachable!("constants are ints and names"),
+        }
+    }
+    write_count(&mut body, chunk.code.len());
+    for op in &chunk.code {
+        body.push(match op {
+            Op::Constant(_) => 0,
+            Op::Nil => 1,
+            Op::Add => 2,
+            Op::Subtract => 3,
+            Op::Multiply => 4,
+            Op::Divide => 5,
+            Op::Remainder => 6,
+            Op::Power => 7,
+            Op::Print => 8,
+            Op::Call { .. } => 9,
+            Op::Pop => 10,
+            Op::Return => 11,
+        });
+        match *op {
+            Op::Constant(index) => write_varint(&mut body, index as u64),
+            Op::Call { name, argc } => {
+                write_varint(&mut body, name as u64);
+                write_varint(&mut body, argc as u64);
+            }
+            _ => {}
+        }
+    }
+    for (span, ticks) in chunk.spans.iter().zip(&chunk.ticks) {
+        write_count(&mut body, span.line);
+        write_count(&mut body, span.col);
+        write_varint(&mut body, *ticks as u64);
+    }
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+    bytes.extend_from_slice(&body);
+    bytes
+}
+
+/// Reads and verifies a compiled program, along with its `#pragma overflow`.
+/// Anything that would trip up the VM is rejected here.
+pub fn decode(bytes: &[u8]) -> Result<(Chunk, Option<Overflow>), String> {
+    if bytes.len() < HEADER_LEN || !is_bytecode(bytes) {
+        return Err("Not a compiled Rickroll program".to_string());
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(format!(
+            "Compiled with bytecode version {}, but this is version {}, rebuild it",
+            version, VERSION
+        ));
+    }
+    let checksum = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+    let body = &bytes[HEADER_LEN..];
+    if crc32(body) != checksum {
+        return Err("Bytecode checksum mismatch, the file is corrupt".to_string());
+    }
+
+    let mut reader = Reader::new(body);
+    let overflow = match reader.byte()? {
+        0 => None,
+        mode => Some(
+            *Overflow::ALL
+                .get(mode as usize - 1)
+                .ok_or("Invalid overflow mode in bytecode")?,
+        ),
+    };
+    let mut chunk = Chunk::default();
+    for _ in 0..reader.count()? {
+        let constant = match reader.byte()? {
+            0 => {
+                let zigzag = u32::try_from(reader.varint()?).map_err(|_| "Invalid constant")?;
+                Value::Int(Int::Small(((zigzag >> 1) as i32) ^ -((zigzag & 1) as i32)))
+            }
+            1 => Value::Int(reader.text()?.parse().map_err(|_| "Invalid constant")?),
+            2 => Value::Str(reader.text()?.into()),
+            tag => return Err(format!("Invalid constant tag {}", tag)),
+        };
+        chunk.constants.push(constant);
+    }
+    let code_len = reader.count()?;
+    for _ in 0..code_len {
+        let op = match reader.byte()? {
+            0 => Op::Constant(reader.index()?),
+            1 => Op::Nil,
+            2 => Op::Add,
+            3 => Op::Subtract,
+            4 => Op::Multiply,
+            5 => Op::Divide,
+            6 => Op::Remainder,
+            7 => Op::Power,
+            8 => Op::Print,
+            9 => Op::Call {
+                name: reader.index()?,
+                argc: u16::try_from(reader.varint()?).map_err(|_| "Invalid argument count")?,
+            },
+            10 => Op::Pop,
+            11 => Op::Return,
+            opcode => return Err(format!("Invalid opcode {}", opcode)),
+        };
+        chunk.code.push(op);
+    }
+    for _ in 0..code_len {
+        chunk.spans.push(Span {
+            line: reader.count()?,
+            col: reader.count()?,
+        });
+        chunk.ticks.push(reader.index()?);
+    }
+    if !reader.is_empty() {
+        return Err("Trailing bytes after bytecode".to_string());
+    }
+    verify(&chunk)?;
+    Ok((chunk, overflow))
+}
+
+/// Checks that constants are used as what they are, and that the stack
+/// never underflows and holds exactly the program's value at `Return`.
+fn verify(chunk: &Chunk) -> Result<(), String> {
+    let mut depth = 0usize;
+    for (offset, op) in chunk.code.iter().enumerate() {
+        let (pops, pushes) = match *op {
+            Op::Constant(index) => match chunk.constants.get(index as usize) {
+                Some(Value::Int(_)) => (0, 1),
+                _ => return Err(format!("Invalid constant at {:04}", offset)),
+            },
+            Op::Call { name, argc } => match chunk.constants.get(name as usize) {
+                Some(Value::Str(_)) => (argc as usize, 1),
+                _ => return Err(format!("Invalid function name at {:04}", offset)),
+            },
+            Op::Nil => (0, 1),
+            Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Remainder | Op::Power => {
+                (2, 1)
+            }
+            Op::Print => (1, 1),
+            Op::Pop => (1, 0),
+            Op::Return if depth == 1 && offset == chunk.code.len() - 1 => return Ok(()),
+            Op::Return => return Err(format!("Misplaced return at {:04}", offset)),
+        };
+        depth = depth
+            .checked_sub(pops)
+            .ok_or_else(|| format!("Stack underflow at {:04}", offset))?
+            + pushes;
+    }
+    Err("Bytecode doesn't end with a return".to_string())
+}
+
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub(crate) fn write_count(out: &mut Vec<u8>, count: usize) {
+    write_varint(out, count as u64);
+}
+
+pub(crate) fn write_text(out: &mut Vec<u8>, text: &str) {
+    write_count(out, text.len());
+    out.extend_from_slice(text.as_bytes());
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self
+            .bytes
+            .get(self.pos)
+            .ok_or("Unexpected end of bytecode")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Invalid number in bytecode".to_string())
+    }
+
+    pub(crate) fn count(&mut self) -> Result<usize, String> {
+        usize::try_from(self.varint()?).map_err(|_| "Invalid count in bytecode".to_string())
+    }
+
+    pub(crate) fn index(&mut self) -> Result<u32, String> {
+        u32::try_from(self.varint()?).map_err(|_| "Invalid index in bytecode".to_string())
+    }
+
+    pub(crate) fn text(&mut self) -> Result<&'a str, String> {
+        let len = self.count()?;
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or("Unexpected end of bytecode")?;
+        let text = std::str::from_utf8(&self.bytes[self.pos..end])
+            .map_err(|_| "Invalid UTF-8 in bytecode")?;
+        self.pos = end;
+        Ok(text)
+    }
+}
+
+/// CRC-32 (IEEE), as used by zip and PNG.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compiled(source: &str) -> Vec<u8> {
+        let program = crate::parse(source).unwrap();
+        encode(&Chunk::compile(&program).unwrap(), program.overflow())
+    }
+
+    // a file with a valid header around `body`, so only the body's checked
+    fn sealed(body: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&crc32(body).to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[test]
+    fn round_trips() {
+        let source = "#pragma overflow wrapping\nprint int(input()) * 99999999999999999999\n7 - 9";
+        let program = crate::parse(source).unwrap();
+        let chunk = Chunk::compile(&program).unwrap();
+        let (decoded, overflow) = decode(&encode(&chunk, program.overflow())).unwrap();
+        assert_eq!(decoded.to_string(), chunk.to_string());
+        assert_eq!(decoded.spans, chunk.spans);
+        assert_eq!(decoded.ticks, chunk.ticks);
+        assert_eq!(overflow, Some(Overflow::Wrapping));
+    }
+
+    #[test]
+    fn rejects_corruption() {
+        let bytes =
+            compiled("#pragma overflow saturating\nprint int(input()) * 99999999999999999999");
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "cut at {}", len);
+        }
+        for i in 0..bytes.len() {
+            for bit in 0..8 {
+                let mut corrupt = bytes.clone();
+                corrupt[i] ^= 1 << bit;
+                assert!(decode(&corrupt).is_err(), "byte {} bit {}", i, bit);
+            }
+        }
+        let mut old = bytes.clone();
+        old[4] = 1;
+        assert_eq!(
+            decode(&old).err().unwrap(),
+            "Compiled with bytecode version 1, but this is version 2, rebuild it"
+        );
+    }
+
+    #[test]
+    fn verifies_what_it_reads() {
+        // overflow, constants, code, then a line, column and ticks per instruction
+        let cases: [(&[u8], &str); 5] = [
+            (&[9, 0, 1, 11, 1, 1, 0], "Invalid overflow mode in bytecode"),
+            (
+                &[0, 0, 2, 2, 11, 1, 1, 0, 1, 1, 0],
+                "Stack underflow at 0000",
+            ),
+            (
+                &[0, 0, 2, 0, 5, 11, 1, 1, 0, 1, 1, 0],
+                "Invalid constant at 0000",
+            ),
+            (
+                &[0, 0, 2, 1, 1, 1, 1, 0, 1, 1, 0],
+                "Bytecode doesn't end with a return",
+            ),
+            (
+                &[0, 0, 2, 1, 11, 1, 1, 0, 1, 1, 0, 0],
+                "Trailing bytes after bytecode",
+            ),
+        ];
+        for (body, message) in cases {
+            assert_eq!(decode(&sealed(body)).err().unwrap(), message);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+// Functions compiled programs call back into.
+// `compiler::run` maps them into the JIT, and they share their code with the builtins,
+// so both backends fail with the same errors.
+
+use std::io;
+
+use crate::arith::Overflow;
+use crate::bigint::{self, Int};
+use crate::builtins;
+use crate::interpreter::{ErrorKind, RuntimeError};
+use crate::lexer::{Span, Token};
+use crate::value::Value;
+
+// lines and columns come as the IR's `i32`s, and are never negative
+fn span(line: i32, col: i32) -> Span {
+    Span {
+        line: line as usize,
+        col: col as usize,
+    }
+}
+
+// compiled code has no source at hand, so the report has no excerpt
+fn fail(err: RuntimeError) -> ! {
+    eprintln!("{}", err.report(None));
+    std::process::exit(1)
+}
+
+/// An error from the builtin `name`, called at `span`.
+fn in_call(kind: ErrorKind, name: &str, span: Span) -> RuntimeError {
+    RuntimeError::new(kind, span).in_call(name, span)
+}
+
+/// `base ** exponent`, by squaring like the interpreter, so big exponents are quick
+pub extern "C" fn rickroll_pow(mode: i32, base: i32, exponent: i32, line: i32, col: i32) -> i32 {
+    let overflow = Overflow::ALL[mode as usize];
+    let (base, exponent) = (Int::Small(base), Int::Small(exponent));
+    match overflow.apply(&Token::Pow, &base, &exponent, &mut bigint::unmetered) {
+        Ok(Int::Small(value)) => value,
+        Ok(value) => unreachable!("`**` returned {:?}", value),
+        Err(kind) => fail(RuntimeError::new(kind, span(line, col))),
+    }
+}
+
+/// `int(input())`
+pub extern "C" fn rickroll_read_int(
+    // an index into `Overflow::ALL`
+    mode: i32,
+    int_line: i32,
+    int_col: i32,
+    input_line: i32,
+    input_col: i32,
+) -> i32 {
+    let overflow = Overflow::ALL[mode as usize];
+    let line = builtins::call("input", &[], &mut io::stdin().lock(), overflow)
+        .unwrap_or_else(|kind| fail(in_call(kind, "input", span(input_line, input_col))));
+    match builtins::call("int", &[line], &mut io::empty(), overflow) {
+        Ok(Value::Int(Int::Small(value))) => value,
+        // compiled code never runs with `Overflow::Arbitrary`, so `int` always narrows
+        Ok(value) => unreachable!("`int` returned {:?}", value),
+        Err(kind) => fail(in_call(kind, "int", span(int_line, int_col))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pow_squares() {
+        let mode = |overflow: Overflow| overflow.index() as i32;
+        // a multiplication loop would take 2e9 steps
+        assert_eq!(
+            rickroll_pow(mode(Overflow::Wrapping), 2, 2_000_000_000, 1, 1),
+            0
+        );
+        assert_eq!(
+            rickroll_pow(mode(Overflow::Wrapping), 3, 2_000_000_001, 1, 1),
+            1_897_082_883
+        );
+        assert_eq!(
+            rickroll_pow(mode(Overflow::Saturating), -2, 2_000_000_001, 1, 1),
+            i32::MIN
+        );
+        assert_eq!(rickroll_pow(mode(Overflow::Checked), -3, 3, 1, 1), -27);
+    }
+}
@@ -0,0 +1,386 @@
+// Snapshots of a program loaded into an `Interpreter`, taken between statements.
+//
+// That's all the state there is: Rickroll has no variables or user functions,
+// so between statements nothing is on the call stack and no value is live but
+// the last statement's. A snapshot is the program counter, that value and what
+// the run has used, so restoring it in a new process carries on where it left off.
+//
+//   magic        b"RRS\0"
+//   version      u16, little endian
+//   checksum     u32, little endian, CRC-32 of everything after it
+//   program      u32, CRC-32 of the program's statements compiled to `.rrc`, as
+//                restoring has to be given the same program again
+//   pc           the next statement
+//   last         0 if no statement has run yet, else 1 and a value
+//   failed       0, or 1 if the statement at pc failed, and the error: its message,
+//                line and column, then its frames (count, then name, line, column)
+//   overflow     the mode, as in `.rrc` files
+//   usage        steps taken, bytes allocated
+//
+// Numbers and text are written as in `.rrc` files. Values are a tag and their contents:
+// 0 nil, 1 small int (zigzag), 2 big int (decimal text), 3 float (f64 bits, little endian),
+// 4 bool (a byte), 5 string, 6 list (count, values), 7 map (count, keys and values),
+// 8 function (its name). Lists and maps nest at most `MAX_DEPTH` deep.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::arith::Overflow;
+use crate::bigint::Int;
+use crate::bytecode::Chunk;
+use crate::interpreter::{ErrorKind, RuntimeError};
+use crate::lexer::Span;
+use crate::parser::AST;
+use crate::rrc::{self, Reader};
+use crate::value::Value;
+
+pub const MAGIC: &[u8; 4] = b"RRS\0";
+pub const VERSION: u16 = 2;
+const HEADER_LEN: usize = 10;
+/// How deep decoded values can nest, so a crafted file can't overflow the stack
+const MAX_DEPTH: usize = 512;
+
+/// What's saved of an interpreter's loaded program.
+pub(crate) struct State {
+    pub program: u32,
+    pub pc: usize,
+    pub last: Option<Value>,
+    /// Restored with its message as an `ErrorKind::Replayed`
+    pub failed: Option<RuntimeError>,
+    pub overflow: Overflow,
+    pub steps: u64,
+    pub allocated: usize,
+}
+
+/// Identifies a program, so a snapshot isn't resumed in a different one.
+pub(crate) fn fingerprint(statements: &[AST]) -> u32 {
+    let mut bytes = Vec::new();
+    for statement in statements {
+        match Chunk::lower(statement) {
+            Ok(chunk) => bytes.extend(rrc::encode(&chunk, None)),
+            // deterministic all the same
+            Err(message) => bytes.extend(message.into_bytes()),
+        }
+    }
+    rrc::crc32(&bytes)
+}
+
+pub(crate) fn encode(state: &State) -> Vec<u8> {
+    let mut body = state.program.to_le_bytes().to_vec();
+    rrc::write_count(&mut body, state.pc);
+    match &state.last {
+        Some(value) => {
+            body.push(1);
+            write_value(&mut body, value);
+        }
+        None => body.push(0),
+    }
+    match &state.failed {
+        Some(err) => {
+            body.push(1);
+            rrc::write_text(&mut body, &err.kind.to_string());
+            write_span(&mut body, err.span);
+            rrc::write_count(&mut body, err.frames.len());
+            for frame in &err.frames {
+                rrc::write_text(&mut body, &frame.name);
+                write_span(&mut body, frame.call);
+            }
+        }
+        None => body.push(0),
+    }
+    body.push(state.overflow.index());
+    rrc::write_varint(&mut body, state.steps);
+    rrc::write_count(&mut body, state.allocated);
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&rrc::crc32(&body).to_le_bytes());
+    bytes.extend_from_slice(&body);
+    bytes
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> Result<State, String> {
+    if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) {
+        return Err("Not an interpreter snapshot".to_string());
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(format!(
+            "Snapshot version {}, but this is version {}",
+            version, VERSION
+        ));
+    }
+    let checksum = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+    let body = &bytes[HEADER_LEN..];
+    if rrc::crc32(body) != checksum {
+        return Err("Snapshot checksum mismatch, the file is corrupt".to_string());
+    }
+
+    let mut reader = Reader::new(body);
+    let program = u32::from_le_bytes([
+        reader.byte()?,
+        reader.byte()?,
+        reader.byte()?,
+        reader.byte()?,
+    ]);
+    let pc = reader.count()?;
+    let last = match reader.byte()? {
+        0 => None,
+        1 => Some(read_value(&mut reader, 0)?),
+        _ => return Err("Invalid snapshot".to_string()),
+    };
+    let failed = match reader.byte()? {
+        0 => None,
+        1 => {
+            let kind = ErrorKind::Replayed(reader.text()?.to_string());
+            let mut err = RuntimeError::new(kind, read_span(&mut reader)?);
+            for _ in 0..reader.count()? {
+                let name = reader.text()?.to_string();
+                let call = read_span(&mut reader)?;
+                err = err.in_call(&name, call);
+            }
+            Some(err)
+        }
+        _ => return Err("Invalid snapshot".to_string()),
+    };
+    let overflow = *Overflow::ALL
+        .get(reader.byte()? as usize)
+        .ok_or("Invalid overflow mode in snapshot")?;
+    let steps = reader.varint()?;
+    let allocated = reader.count()?;
+    if !reader.is_empty() {
+        return Err("Trailing bytes after snapshot".to_string());
+    }
+    Ok(State {
+        program,
+        pc,
+        last,
+        failed,
+        overflow,
+        steps,
+        allocated,
+    })
+}
+
+fn write_span(out: &mut Vec<u8>, span: Span) {
+    rrc::write_count(out, span.line);
+    rrc::write_count(out, span.col);
+}
+
+fn read_span(reader: &mut Reader) -> Result<Span, String> {
+    Ok(Span {
+        line: reader.count()?,
+        col: reader.count()?,
+    })
+}
+
+pub(crate) fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Nil => out.push(0),
+        &Value::Int(Int::Small(value)) => {
+            out.push(1);
+            rrc::write_varint(out, ((value << 1) ^ (value >> 31)) as u32 as u64);
+        }
+        Value::Int(value) => {
+            out.push(2);
+            rrc::write_text(out, &value.to_string());
+        }
+        Value::Float(value) => {
+            out.push(3);
+            out.extend_from_slice(&value.to_bits().to_le_bytes());
+        }
+        Value::Bool(value) => out.extend_from_slice(&[4, *value as u8]),
+        Value::Str(text) => {
+            out.push(5);
+            rrc::write_text(out, text);
+        }
+        Value::List(values) => {
+            out.push(6);
+            rrc::write_count(out, values.len());
+            for value in values.iter() {
+                write_value(out, value);
+            }
+        }
+        Value::Map(entries) => {
+            out.push(7);
+            rrc::write_count(out, entries.len());
+            for (key, value) in entries.iter() {
+                rrc::write_text(out, key);
+                write_value(out, value);
+            }
+        }
+        Value::Function(name) => {
+            out.push(8);
+            rrc::write_text(out, name);
+        }
+    }
+}
+
+/// Reads a value nested `depth` deep in another.
+pub(crate) fn read_value(reader: &mut Reader, depth: usize) -> Result<Value, String> {
+    let tag = reader.byte()?;
+    if matches!(tag, 6 | 7) && depth >= MAX_DEPTH {
+        return Err("Value nested too deeply in snapshot".to_string());
+    }
+    Ok(match tag {
+        0 => Value::Nil,
+        1 => {
+            let zigzag = u32::try_from(reader.varint()?).map_err(|_| "Invalid int in snapshot")?;
+            Value::Int(Int::Small(((zigzag >> 1) as i32) ^ -((zigzag & 1) as i32)))
+        }
+        2 => Value::Int(
+            reader
+                .text()?
+                .parse()
+                .map_err(|_| "Invalid int in snapshot")?,
+        ),
+        3 => {
+            let mut bits = [0; 8];
+            for byte in &mut bits {
+                *byte = reader.byte()?;
+            }
+            Value::Float(f64::from_bits(u64::from_le_bytes(bits)))
+        }
+        4 => Value::Bool(reader.byte()? != 0),
+        5 => Value::Str(reader.text()?.into()),
+        6 => {
+            // not `collect`, which would trust the count to allocate up front
+            let mut values = Vec::new();
+            for _ in 0..reader.count()? {
+                values.push(read_value(reader, depth + 1)?);
+            }
+            Value::List(values.into())
+        }
+        7 => {
+            let mut entries = BTreeMap::new();
+            for _ in 0..reader.count()? {
+                let key = reader.text()?.to_string();
+                entries.insert(key, read_value(reader, depth + 1)?);
+            }
+            Value::Map(Arc::new(entries))
+        }
+        8 => Value::Function(reader.text()?.into()),
+        tag => return Err(format!("Invalid value tag {} in snapshot", tag)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{Interpreter, Status};
+    use crate::replay::{self, Recording};
+
+    fn with_header(magic: &[u8; 4], version: u16, body: &[u8]) -> Vec<u8> {
+        let mut bytes = magic.to_vec();
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend_from_slice(&rrc::crc32(body).to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    // a list in a list in a list..., `depth` deep
+    fn nested(depth: usize) -> Vec<u8> {
+        let mut bytes = [6, 1].repeat(depth);
+        bytes.push(0);
+        bytes
+    }
+
+    fn snapshot_with(value: &[u8]) -> Vec<u8> {
+        let mut body = vec![0, 0, 0, 0, 0, 1];
+        body.extend_from_slice(value);
+        body.extend_from_slice(&[0, 0, 0, 0]);
+        with_header(MAGIC, VERSION, &body)
+    }
+
+    #[test]
+    fn restoring_carries_on() {
+        let source = "#pragma overflow arbitrary\nprint 2 ** 70\nprint int(input()) + 1\n3 ** 50";
+        let program = crate::parse(source).unwrap();
+        let mut interpreter = Interpreter::with_io(Vec::new(), &b"41\n"[..]);
+        interpreter.load(&program);
+        interpreter.step().unwrap();
+        let snapshot = interpreter.snapshot().unwrap();
+        while let Status::Paused { .. } = interpreter.step().unwrap() {}
+
+        // a new interpreter, with the input the first one hadn't read yet
+        let mut restored = Interpreter::with_io(Vec::new(), &b"41\n"[..]);
+        restored.restore(&program, &snapshot).unwrap();
+        assert_eq!(
+            restored.last_value().unwrap().to_string(),
+            "1180591620717411303424"
+        );
+        let finished = loop {
+            if let Status::Finished(value) = restored.step().unwrap() {
+                break value;
+            }
+        };
+        assert_eq!(finished.to_string(), "717897987691852588770249");
+        assert_eq!(restored.output(), b"42\n");
+        assert_eq!(interpreter.output(), b"1180591620717411303424\n42\n");
+        assert_eq!(restored.stats().steps, interpreter.stats().steps);
+    }
+
+    #[test]
+    fn rejects_corruption() {
+        let program = crate::parse("print 1\nprint 1 / 0").unwrap();
+        let mut interpreter = Interpreter::with_io(Vec::new(), &b""[..]);
+        interpreter.load(&program);
+        interpreter.step().unwrap();
+        interpreter.step().unwrap_err();
+        let snapshot = interpreter.snapshot().unwrap();
+        Interpreter::new().restore(&program, &snapshot).unwrap();
+
+        for len in 0..snapshot.len() {
+            assert!(decode(&snapshot[..len]).is_err(), "cut at {}", len);
+        }
+        for i in 0..snapshot.len() {
+            for bit in 0..8 {
+                let mut corrupt = snapshot.clone();
+                corrupt[i] ^= 1 << bit;
+                assert!(decode(&corrupt).is_err(), "byte {} bit {}", i, bit);
+            }
+        }
+        let other = crate::parse("print 2\nprint 1 / 0").unwrap();
+        let err = Interpreter::new().restore(&other, &snapshot).err().unwrap();
+        assert_eq!(err, "The snapshot is of a different program");
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let last = decode(&snapshot_with(&nested(MAX_DEPTH))).unwrap().last;
+        assert!(matches!(last, Some(Value::List(_))));
+
+        let err = decode(&snapshot_with(&nested(1_000_000))).err().unwrap();
+        assert_eq!(err, "Value nested too deeply in snapshot");
+
+        let mut body = vec![1, 1, b'f', 0];
+        body.extend_from_slice(&nested(1_000_000));
+        let err = Recording::decode(&with_header(replay::MAGIC, replay::VERSION, &body))
+            .err()
+            .unwrap();
+        assert_eq!(err, "Value nested too deeply in snapshot");
+    }
+
+    #[test]
+    fn failures_are_kept() {
+        let program = crate::parse("print 1\nprint 1 / 0\nprint 2").unwrap();
+        let mut interpreter = Interpreter::with_io(Vec::new(), &b""[..]);
+        interpreter.load(&program);
+        interpreter.step().unwrap();
+        let err = interpreter.step().unwrap_err();
+        assert_eq!(err.to_string(), "runtime error at 2:9: division by zero");
+        // it stays failed, and says so again
+        assert_eq!(interpreter.step().unwrap_err().to_string(), err.to_string());
+        assert_eq!(interpreter.output(), b"1\n");
+
+        let snapshot = interpreter.snapshot().unwrap();
+        let mut restored = Interpreter::with_io(Vec::new(), &b""[..]);
+        match restored.restore(&program, &snapshot).unwrap() {
+            Status::Failed(restored) => assert_eq!(restored.to_string(), err.to_string()),
+            status => panic!("restored as {:?}", status),
+        }
+        assert_eq!(restored.step().unwrap_err().to_string(), err.to_string());
+        assert!(restored.output().is_empty());
+    }
+}
@@ -0,0 +1,387 @@
+// Programs run every way there is to run them, which all have to agree: the
+// interpreter, the VM, the VM on bytecode that went through a `.rrc` file, and
+// the interpreter after `-O`, in every overflow mode.
+
+use crate::arith::Overflow;
+use crate::bytecode::Chunk;
+use crate::interpreter::Interpreter;
+use crate::vm::Vm;
+use crate::{rrc, Program};
+
+const INPUT: &[u8] = b"7\n2147483647\n";
+
+const PROGRAMS: &[&str] = &[
+    "print 1 + 2 * 3\nprint 2 ** 3 ** 2\nprint (1 + 2) * 3",
+    "print 7 / 2\nprint (0 - 7) / 2\nprint (0 - 7) % 3\nprint 7 % (0 - 3)",
+    "print 2147483647 + 1",
+    "print 0 - 2147483647 - 2",
+    "print 65536 * 65536",
+    "print 2 ** 31\nprint 3 ** 100\nprint 2 ** 0\nprint 2 ** (0 - 1)",
+    "print 1\nprint 1 / 0\nprint 2",
+    "print 5 % (3 - 3)",
+    "print 99999999999999999999 - 99999999999999999998",
+    "print int(input()) * 6\nprint int(input()) + 1\nprint input()",
+    "print float(7) / 2\nprint float(1) + int(2)\nprint int(float(9) / 2)",
+    "print 2 ** 10 * 0 + 5\nprint int(3) * 1 + 0\nprint 0 + 1 * int(4) ** 1",
+    "print nope(1)",
+    "print int(1, 2)",
+    "int(5) * 2",
+];
+
+/// What a run printed, then what it returned or how it failed.
+fn outcome(
+    output: &[u8],
+    result: Result<crate::Value, crate::RuntimeError>,
+    source: &str,
+) -> String {
+    let result = match result {
+        Ok(value) => format!("= {}", value.repr()),
+        Err(err) => err.report(Some(source)),
+    };
+    format!("{}{}", String::from_utf8_lossy(output), result)
+}
+
+fn interpret(program: &Program, source: &str, overflow: Overflow) -> String {
+    let mut interpreter = Interpreter::with_io(Vec::new(), INPUT).with_overflow(overflow);
+    let result = interpreter.run(program);
+    outcome(interpreter.output(), result, source)
+}
+
+fn run_vm(chunk: &Chunk, source: &str, overflow: Overflow) -> String {
+    let mut output = Vec::new();
+    let result = Vm::with_io(&mut output, INPUT)
+        .with_overflow(overflow)
+        .run(chunk);
+    outcome(&output, result, source)
+}
+
+#[test]
+fn vm_matches_interpreter() {
+    for source in PROGRAMS {
+        let program = crate::parse(source).unwrap();
+        let chunk = Chunk::compile(&program).unwrap();
+        for overflow in Overflow::ALL {
+            assert_eq!(
+                run_vm(&chunk, source, overflow),
+                interpret(&program, source, overflow),
+                "{:?} with {:?}",
+                source,
+                overflow
+            );
+        }
+    }
+}
+
+#[test]
+fn vm_calls_host_functions_like_the_interpreter() {
+    let source = "print add(2, 3)\nprint add(2)\nprint 1";
+    let program = crate::parse(source).unwrap();
+    let chunk = Chunk::compile(&program).unwrap();
+    let add = |a: i64, b: i64| a + b;
+    let mut interpreter = Interpreter::with_io(Vec::new(), INPUT);
+    interpreter.register_fn("add", add);
+    let result = interpreter.run(&program);
+    let expected = outcome(interpreter.output(), result, source);
+    let mut output = Vec::new();
+    let mut vm = Vm::with_io(&mut output, INPUT);
+    vm.register_fn("add", add);
+    let result = vm.run(&chunk);
+    assert_eq!(outcome(&output, result, source), expected);
+    assert!(
+        expected
+            .starts_with("5\nruntime error at 2:7: `add` takes 2 argument(s), but 1 were given"),
+        "{}",
+        expected
+    );
+}
+
+// the VM blames the instruction, which can be below the node the interpreter stopped at
+#[test]
+fn vm_runs_out_of_fuel_where_the_interpreter_does() {
+    let program = crate::parse(PROGRAMS[0]).unwrap();
+    let chunk = Chunk::compile(&program).unwrap();
+    for fuel in 0..20 {
+        let mut interpreter = Interpreter::with_io(Vec::new(), INPUT).with_fuel(fuel);
+        let expected = interpreter
+            .run(&program)
+            .map_err(|err| err.kind.to_string());
+        let mut output = Vec::new();
+        let mut vm = Vm::with_io(&mut output, INPUT).with_fuel(fuel);
+        let result = vm.run(&chunk).map_err(|err| err.kind.to_string());
+        assert_eq!(result, expected, "fuel {}", fuel);
+        assert_eq!(vm.stats().steps, interpreter.stats().steps, "fuel {}", fuel);
+        assert_eq!(output, *interpreter.output(), "fuel {}", fuel);
+    }
+}
+
+#[test]
+fn bytecode_files_run_the_same() {
+    for source in PROGRAMS {
+        let program = crate::parse(source).unwrap();
+        let chunk = Chunk::compile(&program).unwrap();
+        for overflow in Overflow::ALL {
+            let (decoded, pragma) = rrc::decode(&rrc::encode(&chunk, Some(overflow))).unwrap();
+            assert_eq!(pragma, Some(overflow));
+            assert_eq!(decoded.to_string(), chunk.to_string());
+            assert_eq!(
+                run_vm(&decoded, source, overflow),
+                run_vm(&chunk, source, overflow),
+                "{:?} with {:?}",
+                source,
+                overflow
+            );
+        }
+    }
+}
+
+#[test]
+fn optimizing_keeps_output() {
+    for source in PROGRAMS {
+        for overflow in Overflow::ALL {
+            let program = crate::parse(source).unwrap();
+            let expected = interpret(&program, source, overflow);
+            match program.optimize(overflow) {
+                Ok(optimized) => assert_eq!(
+                    interpret(&optimized, source, overflow),
+                    expected,
+                    "{:?} with {:?}",
+                    source,
+                    overflow
+                ),
+                // reported before anything runs, rather than when it's reached
+                Err(diagnostics) => {
+                    assert_eq!(
+                        diagnostics.to_string().split(": ").last(),
+                        Some("Division by zero")
+                    );
+                    assert!(expected.contains("division by zero"), "{}", expected);
+                }
+            }
+        }
+    }
+}
+
+// as deep as an expression can go: a `+` or call per level, and a number at the bottom
+const DEEPEST: usize = crate::parser::MAX_DEPTH - 1;
+
+#[test]
+fn deep_expressions_run_everywhere() {
+    let sources = [
+        format!("print 1{}", " + 1".repeat(DEEPEST)),
+        format!("print {}1{}", "(1 + ".repeat(DEEPEST), ")".repeat(DEEPEST)),
+        format!("print {}1{}", "int(".repeat(DEEPEST), ")".repeat(DEEPEST)),
+        format!(
+            "print {}1{}",
+            "((".repeat(DEEPEST / 2),
+            "))".repeat(DEEPEST / 2)
+        ),
+    ];
+    for source in &sources {
+        let program = crate::parse(source).unwrap();
+        let chunk = Chunk::compile(&program).unwrap();
+        let expected = interpret(&program, source, Overflow::Checked);
+        assert!(!expected.contains("error"), "{}", expected);
+        let mut interpreter = Interpreter::with_io(Vec::new(), INPUT)
+            .with_trace(std::io::sink())
+            .with_profiler();
+        let result = interpreter.run(&program);
+        assert_eq!(outcome(interpreter.output(), result, source), expected);
+        assert_eq!(run_vm(&chunk, source, Overflow::Checked), expected);
+        let optimized = program.optimize(Overflow::Checked).unwrap();
+        assert_eq!(interpret(&optimized, source, Overflow::Checked), expected);
+    }
+}
+
+#[test]
+fn deeper_expressions_are_rejected() {
+    let sources = [
+        format!("print 1{}", " + 1".repeat(DEEPEST + 1)),
+        format!(
+            "print {}1{}",
+            "(1 + ".repeat(DEEPEST + 1),
+            ")".repeat(DEEPEST + 1)
+        ),
+        format!("print 1{}", " + 1".repeat(100_000)),
+        format!("print {}1{}", "(".repeat(100_000), ")".repeat(100_000)),
+        format!("print {}1{}", "int(".repeat(100_000), ")".repeat(100_000)),
+        format!("print 1{}", " ** 1".repeat(100_000)),
+    ];
+    for source in &sources {
+        let err = crate::parse(source).err().unwrap();
+        assert!(
+            err.to_string().ends_with(": Expression nested too deeply"),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+fn every_run_gets_the_whole_budget() {
+    let program = crate::parse(PROGRAMS[0]).unwrap();
+    let chunk = Chunk::compile(&program).unwrap();
+    let mut interpreter = Interpreter::with_io(Vec::new(), INPUT)
+        .with_fuel(100)
+        .with_memory_limit(1024);
+    interpreter.run(&program).unwrap();
+    let first = interpreter.stats();
+    interpreter.run(&program).unwrap();
+    let second = interpreter.stats();
+    assert_eq!(
+        (second.steps, second.allocated),
+        (first.steps, first.allocated)
+    );
+    let mut vm = Vm::with_io(Vec::new(), INPUT).with_fuel(first.steps);
+    vm.run(&chunk).unwrap();
+    vm.run(&chunk).unwrap();
+    assert_eq!(vm.stats().steps, first.steps);
+}
+
+#[test]
+fn overflow_modes_at_the_edges() {
+    let cases = [
+        (
+            "print 2147483647 + 1",
+            [
+                "1:18: integer overflow",
+                "-2147483648",
+                "2147483647",
+                "2147483648",
+            ],
+        ),
+        (
+            "print 0 - 2147483647 - 2",
+            [
+                "1:22: integer overflow",
+                "2147483647",
+                "-2147483648",
+                "-2147483649",
+            ],
+        ),
+        (
+            "print 65536 * 65536",
+            ["1:13: integer overflow", "0", "2147483647", "4294967296"],
+        ),
+    ];
+    for (source, expected) in cases {
+        let program = crate::parse(source).unwrap();
+        let chunk = Chunk::compile(&program).unwrap();
+        for (overflow, expected) in Overflow::ALL.into_iter().zip(expected) {
+            let expected = match overflow {
+                Overflow::Checked => format!("runtime error at {}", expected),
+                _ => format!("{}\n", expected),
+            };
+            for got in [
+                interpret(&program, source, overflow),
+                run_vm(&chunk, source, overflow),
+            ] {
+                assert!(
+                    got.starts_with(&expected),
+                    "{:?} with {:?}: {}",
+                    source,
+                    overflow,
+                    got
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn big_numbers_run_out_of_memory() {
+    let source = "print 2 ** 64\nprint 3 ** 100000\nprint 1";
+    let program = crate::parse(source).unwrap();
+    let chunk = Chunk::compile(&program).unwrap();
+    let mut interpreter = Interpreter::with_io(Vec::new(), INPUT)
+        .with_overflow(Overflow::Arbitrary)
+        .with_memory_limit(1024);
+    let result = interpreter.run(&program);
+    let expected = outcome(interpreter.output(), result, source);
+    let mut output = Vec::new();
+    let mut vm = Vm::with_io(&mut output, INPUT)
+        .with_overflow(Overflow::Arbitrary)
+        .with_memory_limit(1024);
+    let result = vm.run(&chunk);
+    assert_eq!(outcome(&output, result, source), expected);
+    // refused before the power is worked out, not partway through
+    assert!(
+        expected.starts_with(
+            "18446744073709551616\nruntime error at 2:9: out of memory: \
+             25000 more bytes would go over the limit of 1024 (16 in use)\n"
+        ),
+        "{}",
+        expected
+    );
+}
+
+/// Lets a test read what it handed to `with_trace`, which keeps the writer.
+#[derive(Clone, Default)]
+struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl std::io::Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn trace_format() {
+    let program = crate::parse("print 3 + 4\nprint int(2) * 1 / 0").unwrap();
+    let trace = Shared::default();
+    let mut interpreter = Interpreter::with_io(Vec::new(), INPUT).with_trace(trace.clone());
+    interpreter.run(&program).unwrap_err();
+    // each node once it's done, indented by depth; nodes a failure passes through aren't
+    let expected = [
+        "      Num at 1:7 → 3",
+        "      Num at 1:11 → 4",
+        "    BinOp(+) at 1:9 → 3 + 4 = 7",
+        "  Print at 1:1 → 7",
+        "          Num at 2:11 → 2",
+        "        Call(int) at 2:7 → int(2) = 2",
+        "        Num at 2:16 → 1",
+        "      BinOp(*) at 2:14 → 2 * 1 = 2",
+        "      Num at 2:20 → 0",
+        "    BinOp(/) at 2:18 → 2 / 0 = error: division by zero",
+    ];
+    let trace = String::from_utf8(trace.0.take()).unwrap();
+    assert_eq!(trace.lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn report_format() {
+    // the caret is under the column, even with a wider line number or indentation
+    let source = format!("{}print 1 +  half(3)", "print 0\n".repeat(11));
+    let program = crate::parse(&source).unwrap();
+    let mut interpreter = Interpreter::with_io(Vec::new(), INPUT);
+    interpreter.register_fn("half", |n: i64| match n % 2 {
+        0 => Ok(n / 2),
+        _ => Err(crate::ErrorKind::Type(format!("{} is odd", n))),
+    });
+    let err = interpreter.run(&program).unwrap_err();
+    let frames = "runtime error at 12:12: type error: 3 is odd\n  \
+                  in `half`, called at 12:12\n  \
+                  in <main>, at 12:12";
+    assert_eq!(err.report(None), frames);
+    assert_eq!(
+        err.report(Some(&source)),
+        format!(
+            "{}\n    12 | print 1 +  half(3)\n       |            ^",
+            frames
+        )
+    );
+
+    let source = "print 1\n  print 2 / 0";
+    let err = Interpreter::with_io(Vec::new(), INPUT)
+        .run(&crate::parse(source).unwrap())
+        .unwrap_err();
+    assert_eq!(
+        err.report(Some(source)),
+        "runtime error at 2:11: division by zero\n  \
+         in <main>, at 2:11\n    \
+         2 |   print 2 / 0\n      |           ^"
+    );
+}
@@ -0,0 +1,336 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::arith::Overflow;
+use crate::bigint::{Int, Meter};
+use crate::interpreter::ErrorKind;
+use crate::lexer::Token;
+
+/// Anything a Rickroll expression can evaluate to.
+///
+/// Values are immutable once made, so the `Arc`s in them can't form cycles and
+/// reference counting frees everything. `Arc` rather than `Rc` lets compiled
+/// chunks and results cross threads. Mutable lists, records or closures
+/// that capture their environment would break that and need a tracing collector.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Int(Int),
+    Float(f64),
+    Bool(bool),
+    Str(Arc<str>),
+    List(Arc<[Value]>),
+    /// String keys, in order
+    Map(Arc<BTreeMap<String, Value>>),
+    /// A builtin or host function, by name
+    Function(Arc<str>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Function(_) => "function",
+        }
+    }
+
+    /// `Display`ed, with `meter` paying for turning big integers into decimal.
+    pub(crate) fn to_string_metered(&self, meter: &mut Meter) -> Result<String, ErrorKind> {
+        match self {
+            Value::Int(value) => value.to_decimal(meter),
+            _ => Ok(self.to_string()),
+        }
+    }
+
+    /// Like `Display`, but strings are quoted.
+    pub fn repr(&self) -> String {
+        match self {
+            Value::Str(text) => format!("{:?}", text),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Bytes this value keeps on the heap.
+    /// Lists and maps only count their own slots (and keys), their elements were paid for
+    /// when they were made.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Value::Int(value) => value.heap_size(),
+            Value::Str(text) => text.len(),
+            Value::List(values) => values.len() * std::mem::size_of::<Value>(),
+            Value::Map(entries) => entries
+                .keys()
+                .map(|key| key.len() + std::mem::size_of::<(String, Value)>())
+                .sum(),
+            _ => 0,
+        }
+    }
+
+    /// An upper bound on the heap bytes `self.binary(op, rhs, overflow)` allocates,
+    /// cheap enough to check before doing the work.
+    pub(crate) fn binary_size_hint(&self, op: &Token, rhs: &Value, overflow: Overflow) -> usize {
+        match (self, rhs) {
+            (Value::Int(a), Value::Int(b)) if overflow == Overflow::Arbitrary => {
+                let bits = match op {
+                    Token::Plus | Token::Minus => a.bits().max(b.bits()) + 1,
+                    Token::Star => a.bits() + b.bits(),
+                    Token::Pow => match b {
+                        // 0, 1 and -1 stay small
+                        _ if a.bits() <= 1 => 0,
+                        Int::Small(exponent) => a.bits().saturating_mul(*exponent as u64),
+                        Int::Big(_) => u64::MAX,
+                    },
+                    _ => a.bits(),
+                };
+                if bits < 32 {
+                    0
+                } else {
+                    usize::try_from(bits.div_ceil(32) * 4).unwrap_or(usize::MAX)
+                }
+            }
+            (Value::Str(a), Value::Str(b)) => a.len() + b.len(),
+            (Value::List(a), Value::List(b)) => (a.len() + b.len()) * std::mem::size_of::<Value>(),
+            _ => 0,
+        }
+    }
+
+    /// Ints and floats mix, everything else has to match exactly.
+    /// `meter` pays for long big integer arithmetic, see `Overflow::apply`.
+    pub(crate) fn binary(
+        &self,
+        op: &Token,
+        rhs: &Value,
+        overflow: Overflow,
+        meter: &mut Meter,
+    ) -> Result<Value, ErrorKind> {
+        match (self, rhs) {
+            (Value::Int(a), Value::Int(b)) => overflow.apply(op, a, b, meter).map(Value::Int),
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                float_binary(op, self.to_f64().unwrap(), rhs.to_f64().unwrap())
+                    .ok_or_else(|| self.type_error(op, rhs))?
+                    .map(Value::Float)
+            }
+            (Value::Str(a), Value::Str(b)) if matches!(op, Token::Plus) => {
+                Ok(Value::Str(format!("{}{}", a, b).into()))
+            }
+            (Value::List(a), Value::List(b)) if matches!(op, Token::Plus) => {
+                Ok(Value::List(a.iter().chain(b.iter()).cloned().collect()))
+            }
+            _ => Err(self.type_error(op, rhs)),
+        }
+    }
+
+    fn type_error(&self, op: &Token, rhs: &Value) -> ErrorKind {
+        ErrorKind::Type(format!(
+            "unsupported operand types for `{}`: {} and {}",
+            op,
+            self.type_name(),
+            rhs.type_name()
+        ))
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(value) => Some(value.to_f64()),
+            Value::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// `None` if `op` isn't arithmetic.
+fn float_binary(op: &Token, lhs: f64, rhs: f64) -> Option<Result<f64, ErrorKind>> {
+    Some(match op {
+        Token::Plus => Ok(lhs + rhs),
+        Token::Minus => Ok(lhs - rhs),
+        Token::Star => Ok(lhs * rhs),
+        // same rule as for ints, no silent infinities
+        Token::Slash | Token::Percent if rhs == 0.0 => Err(ErrorKind::DivisionByZero),
+        Token::Slash => Ok(lhs / rhs),
+        Token::Percent => Ok(lhs % rhs),
+        Token::Pow => Ok(lhs.powf(rhs)),
+        _ => return None,
+    })
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                self.to_f64() == other.to_f64()
+            }
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Only numbers, strings, bools and lists (element by element) are ordered;
+/// comparing anything else is a type error.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                self.to_f64()?.partial_cmp(&other.to_f64()?)
+            }
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+            (Value::List(a), Value::List(b)) => a.iter().partial_cmp(b.iter()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Int(value) => write!(f, "{}", value),
+            // `{:?}` keeps the `.0`, so 2.0 doesn't print like the int 2
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Str(value) => write!(f, "{}", value),
+            Value::List(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value.repr())?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: {}", key, value.repr())?;
+                }
+                write!(f, "}}")
+            }
+            Value::Function(name) => write!(f, "<function {}>", name),
+        }
+    }
+}
+
+impl From<Int> for Value {
+    fn from(value: Int) -> Self {
+        Value::Int(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bigint::unmetered;
+
+    fn int(value: i32) -> Value {
+        Value::Int(value.into())
+    }
+
+    fn list(values: &[Value]) -> Value {
+        Value::List(values.into())
+    }
+
+    #[test]
+    fn equality() {
+        assert_eq!(int(2), int(2));
+        assert_eq!(int(2), Value::Float(2.0));
+        assert_eq!(Value::Float(2.0), int(2));
+        assert_ne!(int(2), Value::Float(2.5));
+        assert_ne!(Value::Float(f64::NAN), Value::Float(f64::NAN));
+        assert_eq!(Value::Str("a".into()), Value::Str("a".into()));
+        assert_eq!(
+            list(&[int(1), Value::Nil]),
+            list(&[Value::Float(1.0), Value::Nil])
+        );
+        assert_eq!(Value::Nil, Value::Nil);
+        // different types are never equal, even when they print the same
+        assert_ne!(int(1), Value::Bool(true));
+        assert_ne!(int(1), Value::Str("1".into()));
+        assert_ne!(Value::Nil, list(&[]));
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(int(1) < int(2));
+        assert!(int(2) < Value::Float(2.5));
+        assert!(Value::Float(-1.0) < int(0));
+        assert!(Value::Bool(false) < Value::Bool(true));
+        assert!(Value::Str("ab".into()) < Value::Str("b".into()));
+        assert!(list(&[int(1), int(2)]) < list(&[int(1), int(3)]));
+        assert!(list(&[int(1)]) < list(&[int(1), int(0)]));
+        for (a, b) in [
+            (int(1), Value::Str("1".into())),
+            (int(0), Value::Bool(false)),
+            (Value::Nil, Value::Nil),
+            (Value::Float(f64::NAN), int(0)),
+            (list(&[int(1)]), list(&[Value::Nil])),
+            (Value::Function("int".into()), Value::Function("int".into())),
+        ] {
+            assert_eq!(a.partial_cmp(&b), None, "{} and {}", a.repr(), b.repr());
+        }
+    }
+
+    #[test]
+    fn mixing_types_is_a_type_error() {
+        let binary = |a: Value, op, b: Value| {
+            a.binary(&op, &b, Overflow::Checked, &mut unmetered)
+                .map(|value| value.repr())
+                .map_err(|kind| kind.to_string())
+        };
+        assert_eq!(
+            binary(int(1), Token::Plus, Value::Float(0.5)),
+            Ok("1.5".into())
+        );
+        assert_eq!(
+            binary(Value::Str("a".into()), Token::Plus, Value::Str("b".into())),
+            Ok("\"ab\"".into())
+        );
+        assert_eq!(
+            binary(int(1), Token::Plus, Value::Str("1".into())),
+            Err("type error: unsupported operand types for `+`: int and string".into())
+        );
+        assert_eq!(
+            binary(Value::Str("a".into()), Token::Star, int(3)),
+            Err("type error: unsupported operand types for `*`: string and int".into())
+        );
+        assert_eq!(
+            binary(Value::Str("a".into()), Token::Minus, Value::Str("b".into())),
+            Err("type error: unsupported operand types for `-`: string and string".into())
+        );
+        assert_eq!(
+            binary(Value::Nil, Token::Plus, list(&[])),
+            Err("type error: unsupported operand types for `+`: nil and list".into())
+        );
+    }
+
+    #[test]
+    fn type_errors_are_reported_at_the_operator() {
+        let source = "print 1\nprint 1 + input()";
+        let program = crate::parse(source).unwrap();
+        let mut interpreter = crate::Interpreter::with_io(Vec::new(), &b"2\n"[..]);
+        let err = interpreter.run(&program).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "runtime error at 2:9: type error: unsupported operand types for `+`: int and string"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+use crate::arith::Overflow;
+use crate::budget::{Budget, Stats};
+use crate::bytecode::{Chunk, Op};
+use crate::convert::IntoNative;
+use crate::interpreter::{ErrorKind, RuntimeError, Stdin};
+use crate::natives::Natives;
+use crate::replay::Recording;
+use crate::value::Value;
+
+/// Runs compiled `Chunk`s on a value stack.
+/// Same semantics, output, errors and limits as `Interpreter`, minus tracing and profiling.
+/// Only the span of a fuel error can differ, as a step is taken by the first
+/// instruction of a node rather than by the node itself.
+pub struct Vm<O: Write = io::Stdout, I: BufRead = Stdin> {
+    overflow: Overflow,
+    budget: Budget,
+    output: O,
+    input: I,
+    natives: Natives,
+    stack: Vec<Value>,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Vm::with_io(io::stdout(), Stdin::default())
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl<O: Write, I: BufRead> Vm<O, I> {
+    pub fn with_io(output: O, input: I) -> Self {
+        Vm {
+            overflow: Overflow::default(),
+            budget: Budget::default(),
+            output,
+            input,
+            natives: Natives::default(),
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Stops with `ErrorKind::OutOfFuel` after as many steps as `Interpreter` would take.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.budget.fuel = Some(fuel);
+        self
+    }
+
+    /// Like `Interpreter::with_timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.budget.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.budget.memory_limit = Some(bytes);
+        self
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.budget.stats()
+    }
+
+    /// Like `Interpreter::register_fn`.
+    pub fn register_fn<Args>(&mut self, name: &str, function: impl IntoNative<Args>) {
+        self.natives.register(name, function);
+    }
+
+    /// Like `Interpreter::with_recording`.
+    pub fn with_recording(mut self) -> Self {
+        self.natives.record();
+        self
+    }
+
+    /// Like `Interpreter::with_replay`, without stepping back.
+    pub fn with_replay(mut self, recording: Recording) -> Self {
+        self.natives.replay(recording);
+        self
+    }
+
+    pub fn recording(&self) -> Option<&Recording> {
+        self.natives.recording()
+    }
+
+    /// Like `Interpreter::run`, each run gets the whole budget.
+    pub fn run(&mut self, chunk: &Chunk) -> Result<Value, RuntimeError> {
+        self.budget.restart();
+        let result = self.run_chunk(chunk);
+        self.budget.finish();
+        result
+    }
+
+    fn run_chunk(&mut self, chunk: &Chunk) -> Result<Value, RuntimeError> {
+        self.stack.clear();
+        for ((&op, &span), &ticks) in chunk.code.iter().zip(&chunk.spans).zip(&chunk.ticks) {
+            match self.execute(op, ticks, chunk) {
+                Ok(Some(value)) => return Ok(value),
+                Ok(None) => {}
+                Err(kind) => {
+                    let err = RuntimeError::new(kind, span);
+                    return Err(match op {
+                        Op::Call { name, .. } => {
+                            err.in_call(&chunk.constants[name as usize].to_string(), span)
+                        }
+                        _ => err,
+                    });
+                }
+            }
+        }
+        Ok(Value::Nil)
+    }
+
+    /// `Some` with the program's value once it returns.
+    fn execute(&mut self, op: Op, ticks: u32, chunk: &Chunk) -> Result<Option<Value>, ErrorKind> {
+        self.budget.charge_steps(ticks as u64)?;
+        match op {
+            Op::Constant(index) => {
+                let value = match &chunk.constants[index as usize] {
+                    Value::Int(value) => Value::Int(self.overflow.narrow(value)?),
+                    value => value.clone(),
+                };
+                self.budget.charge(value.heap_size())?;
+                self.stack.push(value);
+            }
+            Op::Nil => self.stack.push(Value::Nil),
+            Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Remainder | Op::Power => {
+                let token = op.token().expect("arithmetic instruction");
+                let rhs = self.pop();
+                let lhs = self.pop();
+                self.budget
+                    .charge(lhs.binary_size_hint(&token, &rhs, self.overflow))?;
+                let budget = &mut self.budget;
+                let meter = &mut |units| budget.work(units);
+                self.stack
+                    .push(lhs.binary(&token, &rhs, self.overflow, meter)?);
+            }
+            Op::Print => {
+                let value = self.stack.last().expect("stack underflow");
+                let budget = &mut self.budget;
+                let text = value.to_string_metered(&mut |units| budget.work(units))?;
+                writeln!(self.output, "{}", text).map_err(|err| ErrorKind::Io(err.to_string()))?;
+            }
+            Op::Call { name, argc } => {
+                let Value::Str(name) = &chunk.constants[name as usize] else {
+                    unreachable!("function names are strings");
+                };
+                let start = self.stack.len() - argc as usize;
+                let value = self.natives.call(
+                    name,
+                    &self.stack[start..],
+                    &mut self.input,
+                    self.overflow,
+                )?;
+                self.stack.truncate(start);
+                self.budget.charge(value.heap_size())?;
+                self.stack.push(value);
+            }
+            Op::Pop => {
+                self.pop();
+            }
+            Op::Return => return Ok(Some(self.pop())),
+        }
+        Ok(None)
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow")
+    }
+}
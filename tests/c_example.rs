@@ -0,0 +1,20 @@
+// Builds the C API and runs `examples/c` against it, see its Makefile.
+#![cfg(feature = "capi")]
+
+use std::process::Command;
+
+#[test]
+fn c_example() {
+    let status = Command::new("make")
+        .args([
+            "-C",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/examples/c"),
+            "test",
+        ])
+        .status()
+        .expect("couldn't run make");
+    assert!(
+        status.success(),
+        "the C example failed, see its output above"
+    );
+}